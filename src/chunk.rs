@@ -0,0 +1,250 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    token::Span,
+    value::{format_number, Number},
+};
+
+// Instructions understood by the VM. Operands follow the opcode byte in the code
+// stream, constant indices are two bytes wide (big endian), slots and counts one.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum OpCode {
+    // constant index
+    Constant,
+    Nil,
+    True,
+    False,
+    // Placeholder stored by `var x;` until the variable is assigned.
+    Uninitialized,
+    Pop,
+    // slot
+    GetLocal,
+    // slot, name constant: for locals that may still be uninitialized
+    GetLocalChecked,
+    // slot
+    SetLocal,
+    // name constant
+    GetGlobal,
+    // name constant
+    DefineGlobal,
+    // name constant
+    SetGlobal,
+    // upvalue index
+    GetUpvalue,
+    // upvalue index, name constant
+    GetUpvalueChecked,
+    // upvalue index
+    SetUpvalue,
+    // name constant
+    GetProperty,
+    // name constant
+    SetProperty,
+    // name constant
+    GetSuper,
+    Equal,
+    NotEqual,
+    Greater,
+    GreaterEqual,
+    Less,
+    LessEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Not,
+    Negate,
+    Print,
+    // forward offset
+    Jump,
+    // forward offset, leaves the condition on the stack
+    JumpIfFalse,
+    // forward offset, pops the condition when it isn't a boolean in the legacy dialect
+    JumpIfNotBool,
+    // backward offset
+    Loop,
+    // argument count
+    Call,
+    // function constant, then an (is_local, index) pair per upvalue
+    Closure,
+    CloseUpvalue,
+    Return,
+    // name constant
+    Class,
+    Inherit,
+    // name constant
+    Method,
+    // element count, the elements are on the stack
+    BuildList,
+    GetIndex,
+    SetIndex,
+    // entry count, the keys and values are on the stack in pairs
+    BuildMap,
+    Stringify,
+}
+
+impl OpCode {
+    const ALL: [OpCode; 47] = [
+        OpCode::Constant,
+        OpCode::Nil,
+        OpCode::True,
+        OpCode::False,
+        OpCode::Uninitialized,
+        OpCode::Pop,
+        OpCode::GetLocal,
+        OpCode::GetLocalChecked,
+        OpCode::SetLocal,
+        OpCode::GetGlobal,
+        OpCode::DefineGlobal,
+        OpCode::SetGlobal,
+        OpCode::GetUpvalue,
+        OpCode::GetUpvalueChecked,
+        OpCode::SetUpvalue,
+        OpCode::GetProperty,
+        OpCode::SetProperty,
+        OpCode::GetSuper,
+        OpCode::Equal,
+        OpCode::NotEqual,
+        OpCode::Greater,
+        OpCode::GreaterEqual,
+        OpCode::Less,
+        OpCode::LessEqual,
+        OpCode::Add,
+        OpCode::Subtract,
+        OpCode::Multiply,
+        OpCode::Divide,
+        OpCode::Not,
+        OpCode::Negate,
+        OpCode::Print,
+        OpCode::Jump,
+        OpCode::JumpIfFalse,
+        OpCode::JumpIfNotBool,
+        OpCode::Loop,
+        OpCode::Call,
+        OpCode::Closure,
+        OpCode::CloseUpvalue,
+        OpCode::Return,
+        OpCode::Class,
+        OpCode::Inherit,
+        OpCode::Method,
+        OpCode::BuildList,
+        OpCode::GetIndex,
+        OpCode::SetIndex,
+        OpCode::BuildMap,
+        OpCode::Stringify,
+    ];
+
+    pub fn from_byte(byte: u8) -> Option<OpCode> {
+        OpCode::ALL.get(byte as usize).copied()
+    }
+
+    // Number of operand bytes following the opcode. `Closure` is additionally followed
+    // by two bytes per upvalue of its function.
+    pub fn operand_len(self) -> usize {
+        match self {
+            OpCode::GetLocalChecked | OpCode::GetUpvalueChecked => 3,
+            OpCode::Constant
+            | OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Jump
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfNotBool
+            | OpCode::Loop
+            | OpCode::Closure
+            | OpCode::Class
+            | OpCode::Method
+            | OpCode::BuildList
+            | OpCode::BuildMap => 2,
+            OpCode::GetLocal
+            | OpCode::SetLocal
+            | OpCode::GetUpvalue
+            | OpCode::SetUpvalue
+            | OpCode::Call => 1,
+            _ => 0,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Constant {
+    Number(Number),
+    String(Rc<str>),
+    Function(Rc<FunctionProto>),
+}
+
+impl fmt::Display for Constant {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Constant::Number(n) => write!(f, "{}", format_number(*n)),
+            Constant::String(s) => write!(f, "{}", s),
+            Constant::Function(function) => write!(f, "{}", function),
+        }
+    }
+}
+
+// Compiled form of a function body. The top-level script is a function without a name.
+#[derive(Debug, Default)]
+pub struct FunctionProto {
+    pub name: String,
+    pub arity: usize,
+    pub upvalue_count: usize,
+    pub chunk: Chunk,
+}
+
+impl fmt::Display for FunctionProto {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.name.is_empty() {
+            write!(f, "<script>")
+        } else {
+            write!(f, "<fn {}>", self.name)
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Chunk {
+    pub code: Vec<u8>,
+    pub constants: Vec<Constant>,
+    // Run-length encoded line table: each entry gives the span of the instructions
+    // starting at its offset up to the next entry.
+    pub spans: Vec<(usize, Span)>,
+}
+
+impl Chunk {
+    pub fn new() -> Chunk {
+        Chunk::default()
+    }
+
+    pub fn write(&mut self, byte: u8, span: Span) {
+        if self.spans.last().map(|(_, last)| *last) != Some(span) {
+            self.spans.push((self.code.len(), span));
+        }
+        self.code.push(byte);
+    }
+
+    pub fn add_constant(&mut self, constant: Constant) -> usize {
+        self.constants.push(constant);
+        self.constants.len() - 1
+    }
+
+    pub fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_be_bytes([self.code[offset], self.code[offset + 1]])
+    }
+
+    // Source region of the instruction covering the given code offset.
+    pub fn span_at(&self, offset: usize) -> Span {
+        let index = self.spans.partition_point(|(start, _)| *start <= offset);
+        self.spans
+            .get(index.wrapping_sub(1))
+            .map(|(_, span)| *span)
+            .unwrap_or_default()
+    }
+
+    pub fn line_at(&self, offset: usize) -> u32 {
+        self.span_at(offset).line
+    }
+}
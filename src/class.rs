@@ -0,0 +1,86 @@
+use std::{cell::RefCell, collections::HashMap, fmt, rc::Rc};
+
+use crate::{function::Function, value::Value};
+
+pub struct Class {
+    pub name: String,
+    pub superclass: Option<Rc<Class>>,
+    pub methods: HashMap<String, Rc<Function>>,
+}
+
+impl Class {
+    pub fn new(
+        name: String,
+        superclass: Option<Rc<Class>>,
+        methods: HashMap<String, Rc<Function>>,
+    ) -> Class {
+        Class {
+            name,
+            superclass,
+            methods,
+        }
+    }
+
+    // Looks the method up in this class first and then along the superclass chain.
+    pub fn find_method(&self, name: &str) -> Option<Rc<Function>> {
+        if let Some(method) = self.methods.get(name) {
+            return Some(method.clone());
+        }
+
+        self.superclass.as_ref()?.find_method(name)
+    }
+
+    pub fn arity(&self) -> usize {
+        self.find_method("init").map_or(0, |init| init.arity())
+    }
+}
+
+impl fmt::Debug for Class {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+pub struct Instance {
+    pub class: Rc<Class>,
+    fields: RefCell<HashMap<String, Value>>,
+}
+
+impl Instance {
+    pub fn new(class: Rc<Class>) -> Instance {
+        Instance {
+            class,
+            fields: RefCell::new(HashMap::new()),
+        }
+    }
+
+    // Fields shadow methods, methods are bound to `this` on access.
+    pub fn get(instance: &Rc<Instance>, name: &str) -> Option<Value> {
+        if let Some(value) = instance.fields.borrow().get(name) {
+            return Some(value.clone());
+        }
+
+        let method = instance.class.find_method(name)?;
+        let bound = method.bind(Value::Instance(instance.clone()));
+        Some(Value::Function(Rc::new(bound)))
+    }
+
+    pub fn set(&self, name: String, value: Value) {
+        self.fields.borrow_mut().insert(name, value);
+    }
+
+    pub fn fields(&self) -> Vec<Value> {
+        self.fields.borrow().values().cloned().collect()
+    }
+
+    // Drops the fields of an instance the cycle collector found unreachable.
+    pub fn clear(&self) {
+        self.fields.borrow_mut().clear();
+    }
+}
+
+impl fmt::Debug for Instance {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} instance", self.class.name)
+    }
+}
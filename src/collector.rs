@@ -0,0 +1,278 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    rc::{Rc, Weak},
+};
+
+use crate::{
+    class::{Class, Instance},
+    environment::Environment,
+    function::Function,
+    map,
+    object::{GcConfig, HeapStats, GC_GROWTH_FACTOR},
+    value::{List, Map, Value},
+};
+
+// Values of the tree-walking interpreter are reference counted, which frees them
+// unless they keep each other alive: a function stored in the scope it closes over,
+// two instances pointing at each other, a list containing itself. Every such cycle
+// passes through a scope, instance, list or map, so the collector keeps a weak
+// handle to each of them and from time to time looks for groups of objects that are
+// only referenced from within the group. Those can't be reached anymore, and clearing
+// their contents breaks the cycles.
+//
+// There are no roots to mark: references the collector can't see, from the
+// interpreter, Rust code or native functions, show up as a reference count higher
+// than the number of references it found between objects.
+pub struct CycleCollector {
+    tracked: Vec<Tracked>,
+    config: GcConfig,
+    next_gc: usize,
+    stats: HeapStats,
+}
+
+enum Tracked {
+    Environment(Weak<Environment>),
+    Instance(Weak<Instance>),
+    List(Weak<RefCell<Vec<Value>>>),
+    Map(Weak<RefCell<map::Map<Value>>>),
+}
+
+// Object found while tracing, holding a strong reference for the collector.
+enum Object {
+    Environment(Rc<Environment>),
+    Instance(Rc<Instance>),
+    List(List),
+    Map(Map),
+    Function(Rc<Function>),
+    Class(Rc<Class>),
+}
+
+struct Node {
+    object: Object,
+    // References to this object from other traced objects.
+    internal: usize,
+    references: Vec<usize>,
+}
+
+impl CycleCollector {
+    pub fn new() -> CycleCollector {
+        CycleCollector::with_config(GcConfig::default())
+    }
+
+    pub fn with_config(config: GcConfig) -> CycleCollector {
+        CycleCollector {
+            tracked: vec![],
+            config,
+            next_gc: config.threshold,
+            stats: HeapStats::default(),
+        }
+    }
+
+    pub fn set_config(&mut self, config: GcConfig) {
+        self.config = config;
+        self.next_gc = config.threshold;
+    }
+
+    // Live counts tracked objects, some of which may have been freed by reference
+    // counting since the last collection.
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub fn track_environment(&mut self, env: &Rc<Environment>) {
+        self.track(Tracked::Environment(Rc::downgrade(env)));
+    }
+
+    // Instances, lists and maps can be part of a cycle, other values are ignored.
+    pub fn track_value(&mut self, value: &Value) {
+        match value {
+            Value::Instance(instance) => self.track(Tracked::Instance(Rc::downgrade(instance))),
+            Value::List(list) => self.track(Tracked::List(Rc::downgrade(list))),
+            Value::Map(map) => self.track(Tracked::Map(Rc::downgrade(map))),
+            _ => {}
+        }
+    }
+
+    fn track(&mut self, tracked: Tracked) {
+        self.tracked.push(tracked);
+        self.stats.live += 1;
+        if self.config.stress || self.stats.live >= self.next_gc {
+            self.collect();
+        }
+    }
+
+    pub fn collect(&mut self) {
+        let mut nodes = vec![];
+        let mut index = HashMap::new();
+        for tracked in std::mem::take(&mut self.tracked) {
+            if let Some(object) = tracked.upgrade() {
+                intern(object, &mut nodes, &mut index);
+            }
+        }
+
+        let mut i = 0;
+        while i < nodes.len() {
+            let mut references = vec![];
+            nodes[i].object.references(|object| references.push(object));
+            for object in references {
+                let j = intern(object, &mut nodes, &mut index);
+                nodes[j].internal += 1;
+                nodes[i].references.push(j);
+            }
+            i += 1;
+        }
+
+        // Objects referenced from outside, not counting the collector's own reference,
+        // keep everything they refer to alive.
+        let mut reachable = vec![false; nodes.len()];
+        let mut pending: Vec<usize> = (0..nodes.len())
+            .filter(|&i| nodes[i].object.strong_count() - 1 > nodes[i].internal)
+            .collect();
+        while let Some(i) = pending.pop() {
+            if !std::mem::replace(&mut reachable[i], true) {
+                pending.extend(&nodes[i].references);
+            }
+        }
+
+        // Containers found while tracing are tracked from now on, which covers the
+        // ones created by native functions inside the values they return.
+        for (node, reachable) in nodes.iter().zip(reachable) {
+            if reachable {
+                self.tracked.extend(node.object.downgrade());
+            } else if node.object.clear() {
+                self.stats.freed += 1;
+            }
+        }
+        drop(nodes);
+
+        self.stats.live = self.tracked.len();
+        self.stats.collections += 1;
+        self.next_gc = (self.stats.live * GC_GROWTH_FACTOR).max(self.config.threshold);
+    }
+}
+
+impl Default for CycleCollector {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Adds the object to the graph unless it's there already, returning its node.
+fn intern(object: Object, nodes: &mut Vec<Node>, index: &mut HashMap<*const (), usize>) -> usize {
+    *index.entry(object.address()).or_insert_with(|| {
+        nodes.push(Node {
+            object,
+            internal: 0,
+            references: vec![],
+        });
+        nodes.len() - 1
+    })
+}
+
+impl Tracked {
+    fn upgrade(&self) -> Option<Object> {
+        match self {
+            Tracked::Environment(env) => env.upgrade().map(Object::Environment),
+            Tracked::Instance(instance) => instance.upgrade().map(Object::Instance),
+            Tracked::List(list) => list.upgrade().map(Object::List),
+            Tracked::Map(map) => map.upgrade().map(Object::Map),
+        }
+    }
+}
+
+impl Object {
+    fn from_value(value: Value) -> Option<Object> {
+        match value {
+            Value::Function(function) => Some(Object::Function(function)),
+            Value::Class(class) => Some(Object::Class(class)),
+            Value::Instance(instance) => Some(Object::Instance(instance)),
+            Value::List(list) => Some(Object::List(list)),
+            Value::Map(map) => Some(Object::Map(map)),
+            Value::Bool(_)
+            | Value::Number(_)
+            | Value::String(_)
+            | Value::Native(_)
+            | Value::Nil => None,
+        }
+    }
+
+    fn address(&self) -> *const () {
+        match self {
+            Object::Environment(env) => Rc::as_ptr(env) as *const (),
+            Object::Instance(instance) => Rc::as_ptr(instance) as *const (),
+            Object::List(list) => Rc::as_ptr(list) as *const (),
+            Object::Map(map) => Rc::as_ptr(map) as *const (),
+            Object::Function(function) => Rc::as_ptr(function) as *const (),
+            Object::Class(class) => Rc::as_ptr(class) as *const (),
+        }
+    }
+
+    fn strong_count(&self) -> usize {
+        match self {
+            Object::Environment(env) => Rc::strong_count(env),
+            Object::Instance(instance) => Rc::strong_count(instance),
+            Object::List(list) => Rc::strong_count(list),
+            Object::Map(map) => Rc::strong_count(map),
+            Object::Function(function) => Rc::strong_count(function),
+            Object::Class(class) => Rc::strong_count(class),
+        }
+    }
+
+    // Calls `f` once for every strong reference the object holds to another object.
+    fn references(&self, mut f: impl FnMut(Object)) {
+        let values = |values: Vec<Value>, f: &mut dyn FnMut(Object)| {
+            values
+                .into_iter()
+                .filter_map(Object::from_value)
+                .for_each(f)
+        };
+        match self {
+            Object::Environment(env) => {
+                let (variables, enclosing) = env.references();
+                values(variables, &mut f);
+                if let Some(enclosing) = enclosing {
+                    f(Object::Environment(enclosing));
+                }
+            }
+            Object::Instance(instance) => {
+                f(Object::Class(instance.class.clone()));
+                values(instance.fields(), &mut f);
+            }
+            Object::List(list) => values(list.borrow().clone(), &mut f),
+            Object::Map(map) => values(map.borrow().values().cloned().collect(), &mut f),
+            Object::Function(function) => f(Object::Environment(function.closure.clone())),
+            Object::Class(class) => {
+                if let Some(superclass) = &class.superclass {
+                    f(Object::Class(superclass.clone()));
+                }
+                for method in class.methods.values() {
+                    f(Object::Function(method.clone()));
+                }
+            }
+        }
+    }
+
+    fn downgrade(&self) -> Option<Tracked> {
+        match self {
+            Object::Environment(env) => Some(Tracked::Environment(Rc::downgrade(env))),
+            Object::Instance(instance) => Some(Tracked::Instance(Rc::downgrade(instance))),
+            Object::List(list) => Some(Tracked::List(Rc::downgrade(list))),
+            Object::Map(map) => Some(Tracked::Map(Rc::downgrade(map))),
+            Object::Function(_) | Object::Class(_) => None,
+        }
+    }
+
+    // Empties an unreachable container, returning false for functions and classes
+    // which can't be changed and are freed once the containers let go of them.
+    fn clear(&self) -> bool {
+        match self {
+            Object::Environment(env) => env.clear(),
+            Object::Instance(instance) => instance.clear(),
+            Object::List(list) => list.borrow_mut().clear(),
+            Object::Map(map) => *map.borrow_mut() = map::Map::new(),
+            Object::Function(_) | Object::Class(_) => return false,
+        }
+        true
+    }
+}
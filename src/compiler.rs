@@ -0,0 +1,713 @@
+use std::rc::Rc;
+
+use crate::{
+    chunk::{Chunk, Constant, FunctionProto, OpCode},
+    error::Diagnostic,
+    expression::{Expression, ExpressionKind},
+    statement::{FunctionDeclaration, Statement, StatementKind},
+    token::{Literal, Span, Token, TokenType},
+};
+
+const MAX_LOCALS: usize = 256;
+const MAX_UPVALUES: usize = 256;
+
+// Translates a resolved program into bytecode for the VM. Scoping rules match the
+// resolver: top-level declarations are globals, everything else lives in stack slots.
+pub struct Compiler {
+    // Innermost function being compiled last.
+    functions: Vec<FunctionState>,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    Script,
+    Function,
+    Method,
+    Initializer,
+}
+
+struct FunctionState {
+    proto: FunctionProto,
+    typ: FunctionType,
+    locals: Vec<Local>,
+    upvalues: Vec<UpvalueSlot>,
+    scope_depth: usize,
+    // Innermost loop last.
+    loops: Vec<LoopState>,
+}
+
+struct LoopState {
+    // Locals declared deeper than this are discarded when jumping out of the body.
+    scope_depth: usize,
+    // Jumps to patch once the end of the body or of the whole loop is known.
+    continues: Vec<usize>,
+    breaks: Vec<usize>,
+}
+
+struct Local {
+    name: String,
+    depth: usize,
+    is_captured: bool,
+    // Declared without an initializer, reads have to check for a value.
+    may_be_uninitialized: bool,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+struct UpvalueSlot {
+    index: u8,
+    is_local: bool,
+    may_be_uninitialized: bool,
+}
+
+enum Variable {
+    Local(u8, bool),
+    Upvalue(u8, bool),
+    Global,
+}
+
+impl FunctionState {
+    fn new(name: &str, typ: FunctionType) -> FunctionState {
+        // Slot zero holds the callee, methods see it as `this`.
+        let receiver = match typ {
+            FunctionType::Method | FunctionType::Initializer => "this",
+            FunctionType::Script | FunctionType::Function => "",
+        };
+
+        FunctionState {
+            proto: FunctionProto {
+                name: name.to_string(),
+                ..FunctionProto::default()
+            },
+            typ,
+            locals: vec![Local {
+                name: receiver.to_string(),
+                depth: 0,
+                is_captured: false,
+                may_be_uninitialized: false,
+            }],
+            upvalues: vec![],
+            scope_depth: 0,
+            loops: vec![],
+        }
+    }
+}
+
+impl Compiler {
+    pub fn new() -> Compiler {
+        Compiler {
+            functions: vec![],
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn compile(
+        &mut self,
+        statements: &[Statement],
+    ) -> Result<Rc<FunctionProto>, Vec<Diagnostic>> {
+        self.functions
+            .push(FunctionState::new("", FunctionType::Script));
+        for stmt in statements {
+            self.statement(stmt);
+        }
+
+        let end = statements.last().map_or(Span::default(), |stmt| stmt.span);
+        let (proto, _) = self.end_function(end);
+
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+
+        Ok(Rc::new(proto))
+    }
+
+    fn statement(&mut self, statement: &Statement) {
+        let span = statement.span;
+        match &statement.kind {
+            StatementKind::Expression(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Pop, span);
+            }
+            StatementKind::Print(expr) => {
+                self.expression(expr);
+                self.emit(OpCode::Print, span);
+            }
+            StatementKind::Variable(name, initializer) => {
+                match initializer {
+                    Some(initializer) => self.expression(initializer),
+                    None => self.emit(OpCode::Uninitialized, name.span),
+                }
+                self.define_variable(name, initializer.is_none());
+            }
+            StatementKind::Block(stmts) => {
+                self.begin_scope();
+                for stmt in stmts {
+                    self.statement(stmt);
+                }
+                self.end_scope(span.last());
+            }
+            StatementKind::If(cond, then_branch, else_branch) => {
+                self.expression(cond);
+                // In the legacy dialect conditions that aren't booleans run neither branch.
+                let not_bool = self.emit_jump(OpCode::JumpIfNotBool, cond.span);
+                let else_jump = self.emit_jump(OpCode::JumpIfFalse, cond.span);
+                self.emit(OpCode::Pop, cond.span);
+                self.statement(then_branch);
+                let end_jump = self.emit_jump(OpCode::Jump, span);
+
+                self.patch_jump(else_jump, span, "if");
+                self.emit(OpCode::Pop, cond.span);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+                self.patch_jump(end_jump, span, "if");
+                self.patch_jump(not_bool, span, "if");
+            }
+            StatementKind::While(cond, body, increment) => {
+                let loop_start = self.chunk().code.len();
+                self.expression(cond);
+                let exit_jump = self.emit_jump(OpCode::JumpIfFalse, cond.span);
+                self.emit(OpCode::Pop, cond.span);
+
+                let scope_depth = self.current().scope_depth;
+                self.current().loops.push(LoopState {
+                    scope_depth,
+                    continues: vec![],
+                    breaks: vec![],
+                });
+                self.statement(body);
+                let state = self.current().loops.pop().expect("loop state was pushed");
+
+                for jump in state.continues {
+                    self.patch_jump(jump, span, "continue");
+                }
+                if let Some(increment) = increment {
+                    self.expression(increment);
+                    self.emit(OpCode::Pop, increment.span);
+                }
+                self.emit_loop(loop_start, span);
+
+                self.patch_jump(exit_jump, span, "while");
+                self.emit(OpCode::Pop, cond.span);
+                // Breaks leave after the condition has been popped.
+                for jump in state.breaks {
+                    self.patch_jump(jump, span, "break");
+                }
+            }
+            StatementKind::Function(declaration) => {
+                // Declared before the body is compiled so the function can call itself.
+                let is_local = self.current().scope_depth > 0;
+                if is_local {
+                    self.add_local(&declaration.name.lexeme, declaration.name.span, false);
+                }
+                self.function(declaration, FunctionType::Function);
+                if !is_local {
+                    let name = self.identifier(&declaration.name);
+                    self.emit_with_u16(OpCode::DefineGlobal, name, declaration.name.span);
+                }
+            }
+            StatementKind::Class(name, superclass, methods) => {
+                self.class(name, superclass.as_ref(), methods, span)
+            }
+            StatementKind::Return(keyword, value) => match value {
+                Some(value) => {
+                    self.expression(value);
+                    self.emit(OpCode::Return, keyword.span);
+                }
+                None => self.emit_return(keyword.span),
+            },
+            StatementKind::Break(keyword) => {
+                self.discard_loop_locals(keyword.span);
+                let jump = self.emit_jump(OpCode::Jump, keyword.span);
+                self.innermost_loop().breaks.push(jump);
+            }
+            StatementKind::Continue(keyword) => {
+                self.discard_loop_locals(keyword.span);
+                let jump = self.emit_jump(OpCode::Jump, keyword.span);
+                self.innermost_loop().continues.push(jump);
+            }
+        }
+    }
+
+    fn innermost_loop(&mut self) -> &mut LoopState {
+        self.current()
+            .loops
+            .last_mut()
+            .expect("the parser keeps 'break' and 'continue' inside loops")
+    }
+
+    // Pops the locals of the scopes a `break` or `continue` jumps out of. They stay
+    // declared, the code after the jump still belongs to those scopes.
+    fn discard_loop_locals(&mut self, span: Span) {
+        let depth = self.innermost_loop().scope_depth;
+        let codes: Vec<OpCode> = self
+            .current()
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| local.depth > depth)
+            .map(|local| {
+                if local.is_captured {
+                    OpCode::CloseUpvalue
+                } else {
+                    OpCode::Pop
+                }
+            })
+            .collect();
+        for code in codes {
+            self.emit(code, span);
+        }
+    }
+
+    fn class(
+        &mut self,
+        name: &Token,
+        superclass: Option<&Expression>,
+        methods: &[Rc<FunctionDeclaration>],
+        span: Span,
+    ) {
+        let name_constant = self.identifier(name);
+        self.emit_with_u16(OpCode::Class, name_constant, name.span);
+        self.define_variable(name, false);
+
+        // The superclass stays on the stack as a local named `super` that methods
+        // capture, the subclass copies its methods before defining its own.
+        if let Some(superclass) = superclass {
+            self.expression(superclass);
+            self.begin_scope();
+            self.add_local("super", superclass.span, false);
+            self.named_variable(&name.lexeme, name.span);
+            self.emit(OpCode::Inherit, superclass.span);
+        }
+
+        self.named_variable(&name.lexeme, name.span);
+        for method in methods {
+            let typ = if method.name.lexeme == "init" {
+                FunctionType::Initializer
+            } else {
+                FunctionType::Method
+            };
+            self.function(method, typ);
+            let method_name = self.identifier(&method.name);
+            self.emit_with_u16(OpCode::Method, method_name, method.name.span);
+        }
+        self.emit(OpCode::Pop, name.span);
+
+        if superclass.is_some() {
+            self.end_scope(span);
+        }
+    }
+
+    fn function(&mut self, declaration: &FunctionDeclaration, typ: FunctionType) {
+        let name = &declaration.name;
+        self.functions.push(FunctionState::new(&name.lexeme, typ));
+        self.current().proto.arity = declaration.params.len();
+
+        self.begin_scope();
+        for param in &declaration.params {
+            self.add_local(&param.lexeme, param.span, false);
+        }
+        for stmt in &declaration.body {
+            self.statement(stmt);
+        }
+        let (proto, upvalues) = self.end_function(declaration.span);
+
+        let function = self.make_constant(Constant::Function(Rc::new(proto)), name);
+        self.emit_with_u16(OpCode::Closure, function, name.span);
+        for upvalue in upvalues {
+            self.emit_byte(upvalue.is_local as u8, name.span);
+            self.emit_byte(upvalue.index, name.span);
+        }
+    }
+
+    fn end_function(&mut self, span: Span) -> (FunctionProto, Vec<UpvalueSlot>) {
+        // The implicit return belongs to the end of the body.
+        self.emit_return(span.last());
+        let mut state = self.functions.pop().expect("a function is being compiled");
+        state.proto.upvalue_count = state.upvalues.len();
+        (state.proto, state.upvalues)
+    }
+
+    fn expression(&mut self, expression: &Expression) {
+        let span = expression.span;
+        match &expression.kind {
+            ExpressionKind::Literal(literal) => match literal {
+                Literal::Number(n) => self.emit_constant(Constant::Number(*n), span),
+                Literal::String(s) => {
+                    self.emit_constant(Constant::String(Rc::from(s.as_str())), span)
+                }
+                Literal::Bool(true) => self.emit(OpCode::True, span),
+                Literal::Bool(false) => self.emit(OpCode::False, span),
+                Literal::Nil => self.emit(OpCode::Nil, span),
+            },
+            ExpressionKind::Grouping(group) => self.expression(group),
+            ExpressionKind::Stringify(value) => {
+                self.expression(value);
+                self.emit(OpCode::Stringify, span);
+            }
+            ExpressionKind::Unary(op, rhs) => {
+                self.expression(rhs);
+                match op.typ {
+                    TokenType::Minus => self.emit(OpCode::Negate, op.span),
+                    TokenType::Not => self.emit(OpCode::Not, op.span),
+                    _ => unreachable!("Not a unary operator: {:?}", op.typ),
+                }
+            }
+            ExpressionKind::Binary(lhs, op, rhs) => {
+                self.expression(lhs);
+                self.expression(rhs);
+                let code = match op.typ {
+                    TokenType::Plus => OpCode::Add,
+                    TokenType::Minus => OpCode::Subtract,
+                    TokenType::Star => OpCode::Multiply,
+                    TokenType::Slash => OpCode::Divide,
+                    TokenType::EqualEqual => OpCode::Equal,
+                    TokenType::NotEqual => OpCode::NotEqual,
+                    TokenType::Greater => OpCode::Greater,
+                    TokenType::GreaterEqual => OpCode::GreaterEqual,
+                    TokenType::Less => OpCode::Less,
+                    TokenType::LessEqual => OpCode::LessEqual,
+                    _ => unreachable!("Not a binary operator: {:?}", op.typ),
+                };
+                self.emit(code, op.span);
+            }
+            ExpressionKind::Logical(lhs, op, rhs) => {
+                self.expression(lhs);
+                if op.typ == TokenType::And {
+                    let end_jump = self.emit_jump(OpCode::JumpIfFalse, op.span);
+                    self.emit(OpCode::Pop, op.span);
+                    self.expression(rhs);
+                    self.patch_jump(end_jump, op.span, &op.lexeme);
+                } else {
+                    let else_jump = self.emit_jump(OpCode::JumpIfFalse, op.span);
+                    let end_jump = self.emit_jump(OpCode::Jump, op.span);
+                    self.patch_jump(else_jump, op.span, &op.lexeme);
+                    self.emit(OpCode::Pop, op.span);
+                    self.expression(rhs);
+                    self.patch_jump(end_jump, op.span, &op.lexeme);
+                }
+            }
+            ExpressionKind::Variable(name, _) => self.named_variable(&name.lexeme, name.span),
+            ExpressionKind::Assign(name, value, _) => {
+                self.expression(value);
+                match self.resolve(&name.lexeme, name.span) {
+                    Variable::Local(slot, _) => {
+                        self.emit_with_byte(OpCode::SetLocal, slot, name.span)
+                    }
+                    Variable::Upvalue(index, _) => {
+                        self.emit_with_byte(OpCode::SetUpvalue, index, name.span)
+                    }
+                    Variable::Global => {
+                        let name_constant = self.identifier(name);
+                        self.emit_with_u16(OpCode::SetGlobal, name_constant, name.span);
+                    }
+                }
+            }
+            ExpressionKind::Call(callee, paren, args) => {
+                self.expression(callee);
+                for arg in args {
+                    self.expression(arg);
+                }
+                // The parser caps argument lists well below 256.
+                self.emit_with_byte(OpCode::Call, args.len() as u8, paren.span);
+            }
+            ExpressionKind::Get(object, name) => {
+                self.expression(object);
+                let name_constant = self.identifier(name);
+                self.emit_with_u16(OpCode::GetProperty, name_constant, name.span);
+            }
+            ExpressionKind::Set(object, name, value) => {
+                self.expression(object);
+                self.expression(value);
+                let name_constant = self.identifier(name);
+                self.emit_with_u16(OpCode::SetProperty, name_constant, name.span);
+            }
+            ExpressionKind::List(elements) => {
+                for element in elements {
+                    self.expression(element);
+                }
+                if elements.len() > u16::MAX as usize {
+                    self.error(span, "[", "Too many elements in a list literal");
+                }
+                self.emit_with_u16(OpCode::BuildList, elements.len() as u16, span);
+            }
+            ExpressionKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.expression(key);
+                    self.expression(value);
+                }
+                if entries.len() > u16::MAX as usize {
+                    self.error(span, "{", "Too many entries in a map literal");
+                }
+                self.emit_with_u16(OpCode::BuildMap, entries.len() as u16, span);
+            }
+            ExpressionKind::Index(object, bracket, index) => {
+                self.expression(object);
+                self.expression(index);
+                self.emit(OpCode::GetIndex, bracket.span);
+            }
+            ExpressionKind::SetIndex(object, bracket, index, value) => {
+                self.expression(object);
+                self.expression(index);
+                self.expression(value);
+                self.emit(OpCode::SetIndex, bracket.span);
+            }
+            ExpressionKind::This(keyword, _) => self.named_variable(&keyword.lexeme, keyword.span),
+            ExpressionKind::Super(keyword, method, _) => {
+                self.named_variable("this", keyword.span);
+                self.named_variable(&keyword.lexeme, keyword.span);
+                let name_constant = self.identifier(method);
+                self.emit_with_u16(OpCode::GetSuper, name_constant, method.span);
+            }
+        }
+    }
+
+    fn named_variable(&mut self, name: &str, span: Span) {
+        match self.resolve(name, span) {
+            Variable::Local(slot, false) => self.emit_with_byte(OpCode::GetLocal, slot, span),
+            Variable::Local(slot, true) => {
+                self.emit_with_byte(OpCode::GetLocalChecked, slot, span);
+                let name_constant = self.string_constant(name, span);
+                self.emit_u16(name_constant, span);
+            }
+            Variable::Upvalue(index, false) => self.emit_with_byte(OpCode::GetUpvalue, index, span),
+            Variable::Upvalue(index, true) => {
+                self.emit_with_byte(OpCode::GetUpvalueChecked, index, span);
+                let name_constant = self.string_constant(name, span);
+                self.emit_u16(name_constant, span);
+            }
+            Variable::Global => {
+                let name_constant = self.string_constant(name, span);
+                self.emit_with_u16(OpCode::GetGlobal, name_constant, span);
+            }
+        }
+    }
+
+    fn define_variable(&mut self, name: &Token, may_be_uninitialized: bool) {
+        if self.current().scope_depth > 0 {
+            self.add_local(&name.lexeme, name.span, may_be_uninitialized);
+            return;
+        }
+
+        let name_constant = self.identifier(name);
+        self.emit_with_u16(OpCode::DefineGlobal, name_constant, name.span);
+    }
+
+    fn add_local(&mut self, name: &str, span: Span, may_be_uninitialized: bool) {
+        if self.current().locals.len() >= MAX_LOCALS {
+            self.error(span, name, "Too many local variables in function");
+            return;
+        }
+
+        let depth = self.current().scope_depth;
+        self.current().locals.push(Local {
+            name: name.to_string(),
+            depth,
+            is_captured: false,
+            may_be_uninitialized,
+        });
+    }
+
+    fn resolve(&mut self, name: &str, span: Span) -> Variable {
+        let function = self.functions.len() - 1;
+        if let Some((slot, uninitialized)) = self.resolve_local(function, name) {
+            return Variable::Local(slot, uninitialized);
+        }
+        if let Some((index, uninitialized)) = self.resolve_upvalue(function, name, span) {
+            return Variable::Upvalue(index, uninitialized);
+        }
+
+        Variable::Global
+    }
+
+    fn resolve_local(&self, function: usize, name: &str) -> Option<(u8, bool)> {
+        let locals = &self.functions[function].locals;
+        let slot = locals.iter().rposition(|local| local.name == name)?;
+        Some((slot as u8, locals[slot].may_be_uninitialized))
+    }
+
+    fn resolve_upvalue(&mut self, function: usize, name: &str, span: Span) -> Option<(u8, bool)> {
+        // The script's locals belong to blocks, never to an enclosing function.
+        if function == 0 {
+            return None;
+        }
+
+        if let Some((slot, uninitialized)) = self.resolve_local(function - 1, name) {
+            self.functions[function - 1].locals[slot as usize].is_captured = true;
+            let upvalue = UpvalueSlot {
+                index: slot,
+                is_local: true,
+                may_be_uninitialized: uninitialized,
+            };
+            return Some((
+                self.add_upvalue(function, upvalue, name, span),
+                uninitialized,
+            ));
+        }
+
+        let (index, uninitialized) = self.resolve_upvalue(function - 1, name, span)?;
+        let upvalue = UpvalueSlot {
+            index,
+            is_local: false,
+            may_be_uninitialized: uninitialized,
+        };
+        Some((
+            self.add_upvalue(function, upvalue, name, span),
+            uninitialized,
+        ))
+    }
+
+    fn add_upvalue(&mut self, function: usize, upvalue: UpvalueSlot, name: &str, span: Span) -> u8 {
+        let upvalues = &mut self.functions[function].upvalues;
+        if let Some(index) = upvalues.iter().position(|u| *u == upvalue) {
+            return index as u8;
+        }
+
+        if upvalues.len() >= MAX_UPVALUES {
+            self.error(span, name, "Too many closure variables in function");
+            return 0;
+        }
+        upvalues.push(upvalue);
+        (upvalues.len() - 1) as u8
+    }
+
+    fn begin_scope(&mut self) {
+        self.current().scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.current().scope_depth -= 1;
+        let depth = self.current().scope_depth;
+
+        while let Some(local) = self.current().locals.last() {
+            if local.depth <= depth {
+                break;
+            }
+            let code = if local.is_captured {
+                OpCode::CloseUpvalue
+            } else {
+                OpCode::Pop
+            };
+            self.emit(code, span);
+            self.current().locals.pop();
+        }
+    }
+
+    fn emit_return(&mut self, span: Span) {
+        if self.current().typ == FunctionType::Initializer {
+            self.emit_with_byte(OpCode::GetLocal, 0, span);
+        } else {
+            self.emit(OpCode::Nil, span);
+        }
+        self.emit(OpCode::Return, span);
+    }
+
+    fn emit_constant(&mut self, constant: Constant, span: Span) {
+        let index = self.add_constant(constant, span, "");
+        self.emit_with_u16(OpCode::Constant, index, span);
+    }
+
+    // Names are stored once per chunk no matter how often they are used.
+    fn identifier(&mut self, name: &Token) -> u16 {
+        self.string_constant(&name.lexeme, name.span)
+    }
+
+    fn string_constant(&mut self, name: &str, span: Span) -> u16 {
+        let existing = self
+            .chunk()
+            .constants
+            .iter()
+            .position(|c| matches!(c, Constant::String(s) if &**s == name));
+        match existing {
+            Some(index) => index as u16,
+            None => self.add_constant(Constant::String(Rc::from(name)), span, name),
+        }
+    }
+
+    fn make_constant(&mut self, constant: Constant, token: &Token) -> u16 {
+        self.add_constant(constant, token.span, &token.lexeme)
+    }
+
+    fn add_constant(&mut self, constant: Constant, span: Span, lexeme: &str) -> u16 {
+        let index = self.chunk().add_constant(constant);
+        if index > u16::MAX as usize {
+            self.error(span, lexeme, "Too many constants in one chunk");
+            return 0;
+        }
+        index as u16
+    }
+
+    fn emit_jump(&mut self, code: OpCode, span: Span) -> usize {
+        self.emit(code, span);
+        self.emit_u16(u16::MAX, span);
+        self.chunk().code.len() - 2
+    }
+
+    // Points the jump operand at `offset` to the next instruction to be emitted.
+    fn patch_jump(&mut self, offset: usize, span: Span, lexeme: &str) {
+        let jump = self.chunk().code.len() - offset - 2;
+        let Ok(jump) = u16::try_from(jump) else {
+            self.error(span, lexeme, "Too much code to jump over");
+            return;
+        };
+
+        let [high, low] = jump.to_be_bytes();
+        let code = &mut self.chunk().code;
+        code[offset] = high;
+        code[offset + 1] = low;
+    }
+
+    fn emit_loop(&mut self, loop_start: usize, span: Span) {
+        self.emit(OpCode::Loop, span);
+        let offset = self.chunk().code.len() - loop_start + 2;
+        let Ok(offset) = u16::try_from(offset) else {
+            self.error(span, "while", "Loop body too large");
+            return;
+        };
+        self.emit_u16(offset, span);
+    }
+
+    fn emit(&mut self, code: OpCode, span: Span) {
+        self.emit_byte(code as u8, span);
+    }
+
+    fn emit_with_byte(&mut self, code: OpCode, operand: u8, span: Span) {
+        self.emit(code, span);
+        self.emit_byte(operand, span);
+    }
+
+    fn emit_with_u16(&mut self, code: OpCode, operand: u16, span: Span) {
+        self.emit(code, span);
+        self.emit_u16(operand, span);
+    }
+
+    fn emit_byte(&mut self, byte: u8, span: Span) {
+        self.chunk().write(byte, span);
+    }
+
+    fn emit_u16(&mut self, value: u16, span: Span) {
+        for byte in value.to_be_bytes() {
+            self.emit_byte(byte, span);
+        }
+    }
+
+    fn error(&mut self, span: Span, lexeme: &str, message: &str) {
+        self.diagnostics.push(Diagnostic::at(span, lexeme, message));
+    }
+
+    fn current(&mut self) -> &mut FunctionState {
+        self.functions
+            .last_mut()
+            .expect("a function is being compiled")
+    }
+
+    fn chunk(&mut self) -> &mut Chunk {
+        &mut self.current().proto.chunk
+    }
+}
+
+impl Default for Compiler {
+    fn default() -> Self {
+        Compiler::new()
+    }
+}
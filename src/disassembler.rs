@@ -0,0 +1,157 @@
+use std::fmt::Write;
+
+use crate::chunk::{Chunk, Constant, FunctionProto, OpCode};
+
+// Lists the function's chunk followed by the chunks of every function nested in it.
+pub fn disassemble(function: &FunctionProto) -> String {
+    let mut out = disassemble_chunk(&function.chunk, &function.to_string());
+    for constant in &function.chunk.constants {
+        if let Constant::Function(nested) = constant {
+            out.push('\n');
+            out.push_str(&disassemble(nested));
+        }
+    }
+    out
+}
+
+// One line per instruction: offset, source line (`|` when unchanged), opcode and
+// its decoded operands, e.g.
+//
+// == <fn add> ==
+// 0000    1 GetLocal              1
+// 0002    | GetLocal              2
+// 0004    | Add
+pub fn disassemble_chunk(chunk: &Chunk, name: &str) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "== {} ==", name);
+
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        offset = disassemble_instruction(chunk, offset, &mut out);
+    }
+    out
+}
+
+// Appends the instruction at `offset` and returns the offset of the next one.
+pub fn disassemble_instruction(chunk: &Chunk, offset: usize, out: &mut String) -> usize {
+    let _ = write!(out, "{:04} ", offset);
+    let line = chunk.line_at(offset);
+    if offset > 0 && line == chunk.line_at(offset - 1) {
+        out.push_str("   | ");
+    } else {
+        let _ = write!(out, "{:4} ", line);
+    }
+
+    let byte = chunk.code[offset];
+    let Some(code) = OpCode::from_byte(byte) else {
+        let _ = writeln!(out, "Unknown opcode {}", byte);
+        return offset + 1;
+    };
+    let name = format!("{:?}", code);
+
+    match code {
+        OpCode::Constant
+        | OpCode::GetGlobal
+        | OpCode::DefineGlobal
+        | OpCode::SetGlobal
+        | OpCode::GetProperty
+        | OpCode::SetProperty
+        | OpCode::GetSuper
+        | OpCode::Class
+        | OpCode::Method => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = writeln!(out, "{:<18} {:4} {}", name, index, constant(chunk, index));
+            offset + 3
+        }
+        OpCode::GetLocal
+        | OpCode::SetLocal
+        | OpCode::GetUpvalue
+        | OpCode::SetUpvalue
+        | OpCode::Call => {
+            let _ = writeln!(out, "{:<18} {:4}", name, chunk.code[offset + 1]);
+            offset + 2
+        }
+        OpCode::BuildList | OpCode::BuildMap => {
+            let _ = writeln!(out, "{:<18} {:4}", name, chunk.read_u16(offset + 1));
+            offset + 3
+        }
+        OpCode::GetLocalChecked | OpCode::GetUpvalueChecked => {
+            let slot = chunk.code[offset + 1];
+            let index = chunk.read_u16(offset + 2);
+            let _ = writeln!(out, "{:<18} {:4} {}", name, slot, constant(chunk, index));
+            offset + 4
+        }
+        OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotBool => {
+            let target = offset + 3 + chunk.read_u16(offset + 1) as usize;
+            let _ = writeln!(out, "{:<18} {:4} -> {:04}", name, offset, target);
+            offset + 3
+        }
+        OpCode::Loop => {
+            let target = (offset + 3).saturating_sub(chunk.read_u16(offset + 1) as usize);
+            let _ = writeln!(out, "{:<18} {:4} -> {:04}", name, offset, target);
+            offset + 3
+        }
+        OpCode::Closure => {
+            let index = chunk.read_u16(offset + 1);
+            let _ = writeln!(out, "{:<18} {:4} {}", name, index, constant(chunk, index));
+
+            let upvalue_count = match chunk.constants.get(index as usize) {
+                Some(Constant::Function(function)) => function.upvalue_count,
+                _ => 0,
+            };
+            let mut offset = offset + 3;
+            for _ in 0..upvalue_count {
+                let kind = if chunk.code[offset] == 1 {
+                    "local"
+                } else {
+                    "upvalue"
+                };
+                let _ = writeln!(
+                    out,
+                    "{:04}    | {:<18} {:>4} {}",
+                    offset,
+                    "",
+                    kind,
+                    chunk.code[offset + 1]
+                );
+                offset += 2;
+            }
+            offset
+        }
+        OpCode::Nil
+        | OpCode::True
+        | OpCode::False
+        | OpCode::Uninitialized
+        | OpCode::Pop
+        | OpCode::Equal
+        | OpCode::NotEqual
+        | OpCode::Greater
+        | OpCode::GreaterEqual
+        | OpCode::Less
+        | OpCode::LessEqual
+        | OpCode::Add
+        | OpCode::Subtract
+        | OpCode::Multiply
+        | OpCode::Divide
+        | OpCode::Not
+        | OpCode::Negate
+        | OpCode::Stringify
+        | OpCode::Print
+        | OpCode::CloseUpvalue
+        | OpCode::Return
+        | OpCode::Inherit
+        | OpCode::GetIndex
+        | OpCode::SetIndex => {
+            let _ = writeln!(out, "{}", name);
+            offset + 1
+        }
+    }
+}
+
+fn constant(chunk: &Chunk, index: u16) -> String {
+    match chunk.constants.get(index as usize) {
+        Some(Constant::String(s)) => format!("{:?}", s),
+        Some(constant) => constant.to_string(),
+        None => "<invalid constant>".to_string(),
+    }
+}
@@ -32,20 +32,10 @@ impl Environment {
             return true;
         }
 
-        let mut scope = self.enclosing.as_ref();
-
-        while let Some(current) = scope {
-            if current
-                .globals
-                .borrow_mut()
-                .insert(name.clone(), value.clone())
-                .is_some()
-            {
-                return true;
-            }
-            scope = current.enclosing.as_ref();
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.update(name, value),
+            None => false,
         }
-        false
     }
 
     pub fn get(&self, name: &str) -> Option<Value> {
@@ -61,6 +51,36 @@ impl Environment {
         None
     }
 
+    pub fn get_at(&self, depth: usize, name: &str) -> Option<Value> {
+        if depth == 0 {
+            return self.globals.borrow().get(name).cloned().flatten();
+        }
+
+        self.enclosing.as_ref()?.get_at(depth - 1, name)
+    }
+
+    pub fn update_at(&self, depth: usize, name: String, value: Option<Value>) -> bool {
+        if depth == 0 {
+            return self.globals.borrow_mut().insert(name, value).is_some();
+        }
+
+        match self.enclosing {
+            Some(ref enclosing) => enclosing.update_at(depth - 1, name, value),
+            None => false,
+        }
+    }
+
+    // Everything the scope refers to, for the cycle collector.
+    pub fn references(&self) -> (Vec<Value>, Option<Rc<Environment>>) {
+        let values = self.globals.borrow().values().flatten().cloned().collect();
+        (values, self.enclosing.clone())
+    }
+
+    // Drops the variables of a scope the cycle collector found unreachable.
+    pub fn clear(&self) {
+        self.globals.borrow_mut().clear();
+    }
+
     pub fn contains(&self, name: &str) -> bool {
         self.globals.borrow().contains_key(name)
             || self.enclosing.as_ref().is_some_and(|e| e.contains(name))
@@ -1,34 +1,460 @@
-use crate::token::{Token, TokenType};
+use std::{
+    fmt::{self, Write},
+    io::IsTerminal,
+};
 
-pub struct Error {
-    pub had_error: bool,
+use crate::token::{Span, Token};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Severity {
+    Error,
+    Warning,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "Error"),
+            Severity::Warning => write!(f, "Warning"),
+        }
+    }
 }
 
-impl Error {
-    pub fn new() -> Error {
-        Error { had_error: false }
+// Problem found while scanning, parsing or resolving. Callers decide how to present it.
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    // Source text the diagnostic points at, empty at the end of input.
+    pub lexeme: String,
+    pub span: Span,
+    pub help: Option<String>,
+}
+
+impl Diagnostic {
+    pub fn error(token: &Token, message: &str) -> Diagnostic {
+        Diagnostic::at(token.span, &token.lexeme, message)
+    }
+
+    // Error at an arbitrary position, for problems found before a token exists.
+    pub fn at(span: Span, lexeme: &str, message: &str) -> Diagnostic {
+        Diagnostic {
+            severity: Severity::Error,
+            message: message.to_string(),
+            lexeme: lexeme.to_string(),
+            span,
+            help: None,
+        }
+    }
+
+    pub fn with_help(mut self, help: &str) -> Diagnostic {
+        self.help = Some(help.to_string());
+        self
+    }
+
+    pub fn is_error(&self) -> bool {
+        self.severity == Severity::Error
+    }
+
+    pub fn line(&self) -> u32 {
+        self.span.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.column
+    }
+
+    // Renders the diagnostic together with the offending source line, e.g.
+    //
+    // error: Expected expression
+    //  --> line 1, column 9
+    //   |
+    // 1 | var a = ;
+    //   |         ^
+    //   = help: ...
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let style = match self.severity {
+            Severity::Error => Style::Error,
+            Severity::Warning => Style::Warning,
+        };
+        let title = self.severity.to_string().to_lowercase();
+        let notes: Vec<String> = self
+            .help
+            .iter()
+            .map(|help| format!("help: {}", help))
+            .collect();
+
+        render_snippet(
+            source,
+            &Snippet {
+                title: &title,
+                message: &self.message,
+                span: self.span,
+                notes: &notes,
+                style,
+            },
+            color,
+        )
     }
+}
 
-    pub fn error(&mut self, token: Token, message: &str) {
-        self.had_error = true;
-        if token.typ == TokenType::Eof {
-            self.report_eof(token.line, message)
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.lexeme.is_empty() {
+            write!(
+                f,
+                "[line {}] {} at end: {}",
+                self.span.line, self.severity, self.message
+            )?;
         } else {
-            self.report_error(token.line, "", message)
+            write!(
+                f,
+                "[line {}] {} at '{}': {}",
+                self.span.line, self.severity, self.lexeme, self.message
+            )?;
         }
+
+        if let Some(help) = &self.help {
+            write!(f, "\n    help: {}", help)?;
+        }
+        Ok(())
     }
+}
 
-    fn report_error(&mut self, line: u32, location: &str, message: &str) {
-        eprintln!("[line {}] Error {}: {}", line, location, message);
+#[derive(Debug, Clone, PartialEq)]
+pub enum RuntimeErrorKind {
+    UndefinedVariable(String),
+    UninitializedVariable(String),
+    UndefinedProperty(String),
+    OperandMustBeNumber,
+    OperandMustBeBoolean,
+    OperandsMustBeNumbers,
+    OperandsMustBeNumbersOrStrings,
+    NotCallable,
+    ArityMismatch {
+        expected: usize,
+        got: usize,
+    },
+    OnlyInstancesHaveProperties,
+    OnlyInstancesHaveFields,
+    SuperclassMustBeClass,
+    TopLevelReturn,
+    StackOverflow,
+    ArgumentType {
+        position: usize,
+        expected: &'static str,
+        got: &'static str,
+    },
+    // Raised by a native function with its own message.
+    Native(String),
+    Io(String),
+    NotIndexable,
+    IndexMustBeInteger,
+    IndexOutOfBounds {
+        index: i64,
+        len: usize,
+    },
+    // Carries the type name of the rejected key.
+    UnhashableKey(&'static str),
+    NanKey,
+    UndefinedKey(String),
+    // Instruction applied to values the compiler never gives it, only possible in a
+    // hand-made bytecode file.
+    InvalidBytecode(&'static str),
+}
+
+impl RuntimeErrorKind {
+    pub fn hint(&self) -> Option<&'static str> {
+        match self {
+            RuntimeErrorKind::UndefinedVariable(_) => {
+                Some("declare the variable with 'var' before using it")
+            }
+            RuntimeErrorKind::UninitializedVariable(_) => {
+                Some("assign a value to the variable before reading it")
+            }
+            RuntimeErrorKind::OperandsMustBeNumbersOrStrings => {
+                Some("'+' adds two numbers or concatenates two strings")
+            }
+            RuntimeErrorKind::NotCallable => Some("only functions and classes can be called"),
+            RuntimeErrorKind::IndexOutOfBounds { .. } => {
+                Some("indices start at 0, negative ones count from the end")
+            }
+            RuntimeErrorKind::UndefinedKey(_) => Some("use 'has' to check for a key first"),
+            RuntimeErrorKind::OnlyInstancesHaveProperties
+            | RuntimeErrorKind::OnlyInstancesHaveFields => {
+                Some("create an instance by calling the class first")
+            }
+            _ => None,
+        }
     }
+}
 
-    fn report_eof(&mut self, line: u32, message: &str) {
-        eprintln!("[line {}] at end: {}", line, message);
+impl fmt::Display for RuntimeErrorKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RuntimeErrorKind::UndefinedVariable(name) => write!(f, "Undefined variable '{}'", name),
+            RuntimeErrorKind::UninitializedVariable(name) => {
+                write!(f, "Usage of uninitialized variable '{}'", name)
+            }
+            RuntimeErrorKind::UndefinedProperty(name) => write!(f, "Undefined property '{}'", name),
+            RuntimeErrorKind::OperandMustBeNumber => write!(f, "Operand must be a number"),
+            RuntimeErrorKind::OperandMustBeBoolean => write!(f, "Operand must be a boolean"),
+            RuntimeErrorKind::OperandsMustBeNumbers => write!(f, "Operands must be numbers"),
+            RuntimeErrorKind::OperandsMustBeNumbersOrStrings => {
+                write!(f, "Operands must be two numbers or two strings")
+            }
+            RuntimeErrorKind::NotCallable => write!(f, "Can only call functions and classes"),
+            RuntimeErrorKind::ArityMismatch { expected, got } => {
+                write!(f, "Expected {} arguments but got {}", expected, got)
+            }
+            RuntimeErrorKind::OnlyInstancesHaveProperties => {
+                write!(f, "Only instances have properties")
+            }
+            RuntimeErrorKind::OnlyInstancesHaveFields => write!(f, "Only instances have fields"),
+            RuntimeErrorKind::SuperclassMustBeClass => write!(f, "Superclass must be a class"),
+            RuntimeErrorKind::TopLevelReturn => write!(f, "Can't return from top-level code"),
+            RuntimeErrorKind::StackOverflow => write!(f, "Stack overflow"),
+            RuntimeErrorKind::ArgumentType {
+                position,
+                expected,
+                got,
+            } => write!(
+                f,
+                "Argument {} must be a {} but got {}",
+                position, expected, got
+            ),
+            RuntimeErrorKind::Native(message) => write!(f, "{}", message),
+            RuntimeErrorKind::Io(err) => write!(f, "I/O error: {}", err),
+            RuntimeErrorKind::NotIndexable => write!(f, "Only lists and maps can be indexed"),
+            RuntimeErrorKind::IndexMustBeInteger => write!(f, "Index must be an integer"),
+            RuntimeErrorKind::IndexOutOfBounds { index, len } => write!(
+                f,
+                "Index {} is out of bounds for a list of length {}",
+                index, len
+            ),
+            RuntimeErrorKind::UnhashableKey(got) => write!(
+                f,
+                "Map keys must be strings, numbers, bools or nil but got {}",
+                got
+            ),
+            RuntimeErrorKind::NanKey => write!(f, "NaN can't be used as a map key"),
+            RuntimeErrorKind::UndefinedKey(key) => write!(f, "Undefined key '{}'", key),
+            RuntimeErrorKind::InvalidBytecode(reason) => write!(f, "Invalid bytecode: {}", reason),
+        }
     }
 }
 
-impl Default for Error {
-    fn default() -> Self {
-        Error::new()
+// Function call that was active when a runtime error unwound through it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TraceFrame {
+    pub function: String,
+    pub line: u32,
+}
+
+#[derive(Debug, Clone)]
+pub struct RuntimeError {
+    pub span: Span,
+    pub kind: RuntimeErrorKind,
+    // Innermost call first.
+    pub trace: Vec<TraceFrame>,
+}
+
+impl RuntimeError {
+    pub fn new(token: &Token, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError::at(token.span, kind)
+    }
+
+    // Error at a source region, used by backends that no longer have the tokens around.
+    pub fn at(span: Span, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError {
+            span,
+            kind,
+            trace: vec![],
+        }
     }
+
+    pub fn line(&self) -> u32 {
+        self.span.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.column
+    }
+
+    pub fn render(&self, source: &str, color: bool) -> String {
+        let mut notes: Vec<String> = self
+            .kind
+            .hint()
+            .iter()
+            .map(|hint| format!("help: {}", hint))
+            .collect();
+        // Runs of identical frames, as left by deep recursion, are shown once.
+        let mut frames = self.trace.iter().peekable();
+        while let Some(frame) = frames.next() {
+            let mut repeated = 0;
+            while frames.next_if_eq(&frame).is_some() {
+                repeated += 1;
+            }
+
+            let mut note = format!("in {}() called from line {}", frame.function, frame.line);
+            if repeated > 0 {
+                let _ = write!(note, " ({} more times)", repeated);
+            }
+            notes.push(note);
+        }
+
+        render_snippet(
+            source,
+            &Snippet {
+                title: "runtime error",
+                message: &self.kind.to_string(),
+                span: self.span,
+                notes: &notes,
+                style: Style::Error,
+            },
+            color,
+        )
+    }
+}
+
+impl fmt::Display for RuntimeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}] RuntimeError: {}", self.span.line, self.kind)?;
+        for frame in &self.trace {
+            write!(
+                f,
+                "\n    in {}() called from line {}",
+                frame.function, frame.line
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for RuntimeError {}
+
+// Reasons a compiled bytecode file can't be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum BytecodeError {
+    NotBytecode,
+    IncompatibleVersion { found: u16, expected: u16 },
+    ChecksumMismatch,
+    Truncated,
+    Malformed(String),
+}
+
+impl fmt::Display for BytecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            BytecodeError::NotBytecode => write!(f, "Not a compiled Lox file"),
+            BytecodeError::IncompatibleVersion { found, expected } => write!(
+                f,
+                "Compiled with an incompatible version of jlox (format {}, expected {}), recompile the script",
+                found, expected
+            ),
+            BytecodeError::ChecksumMismatch => write!(f, "Checksum mismatch, the file is corrupted"),
+            BytecodeError::Truncated => write!(f, "Unexpected end of file"),
+            BytecodeError::Malformed(reason) => write!(f, "Malformed bytecode: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for BytecodeError {}
+
+// Whether rendered diagnostics should be coloured. They're written to standard
+// error, so that's the stream checked rather than standard output: with
+// `jlox script.lox 2> log` the log stays free of escape codes even though the
+// program's own output still goes to the terminal.
+pub fn color_diagnostics() -> bool {
+    std::io::stderr().is_terminal()
+}
+
+#[derive(Clone, Copy)]
+enum Style {
+    Error,
+    Warning,
+    Gutter,
+}
+
+impl Style {
+    fn paint(self, text: &str, color: bool) -> String {
+        if !color {
+            return text.to_string();
+        }
+
+        let code = match self {
+            Style::Error => "1;31",
+            Style::Warning => "1;33",
+            Style::Gutter => "1;34",
+        };
+        format!("\x1b[{}m{}\x1b[0m", code, text)
+    }
+}
+
+struct Snippet<'a> {
+    title: &'a str,
+    message: &'a str,
+    // Region to underline, at least one character is always marked.
+    span: Span,
+    notes: &'a [String],
+    style: Style,
+}
+
+fn render_snippet(source: &str, snippet: &Snippet, color: bool) -> String {
+    let mut out = String::new();
+    let _ = writeln!(
+        out,
+        "{}: {}",
+        snippet.style.paint(snippet.title, color),
+        snippet.message
+    );
+
+    let span = snippet.span;
+    let number = span.line.to_string();
+    let pad = " ".repeat(number.len());
+    let _ = writeln!(
+        out,
+        "{}{} line {}, column {}",
+        pad,
+        Style::Gutter.paint("-->", color),
+        span.line,
+        span.column
+    );
+
+    let source_line = source.lines().nth(span.line.saturating_sub(1) as usize);
+    if let Some(source_line) = source_line {
+        let gutter = Style::Gutter.paint("|", color);
+        let _ = writeln!(out, "{} {}", pad, gutter);
+        let _ = writeln!(
+            out,
+            "{} {} {}",
+            Style::Gutter.paint(&number, color),
+            gutter,
+            source_line
+        );
+
+        // Keep tabs so that the carets line up with the text above them.
+        let start = span.column.saturating_sub(1) as usize;
+        let indent: String = source_line
+            .chars()
+            .take(start)
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Spans reaching past the first line are underlined up to its end.
+        let available = source_line.chars().count().saturating_sub(start);
+        let width = if span.end_line == span.line {
+            span.end_column.saturating_sub(span.column) as usize
+        } else {
+            available
+        };
+        let width = width.min(available).max(1);
+        let carets = snippet.style.paint(&"^".repeat(width), color);
+        let _ = writeln!(out, "{} {} {}{}", pad, gutter, indent, carets);
+    }
+
+    for note in snippet.notes {
+        let _ = writeln!(out, "{} {} {}", pad, Style::Gutter.paint("=", color), note);
+    }
+
+    out
 }
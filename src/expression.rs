@@ -1,12 +1,43 @@
-use crate::token::{Literal, Token};
+use std::cell::Cell;
+
+use crate::token::{Literal, Span, Token};
+
+#[derive(Debug)]
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+impl Expression {
+    pub fn new(kind: ExpressionKind, span: Span) -> Expression {
+        Expression { kind, span }
+    }
+}
 
 #[derive(Debug)]
-pub enum Expression {
+pub enum ExpressionKind {
     Binary(Box<Expression>, Token, Box<Expression>),
     Unary(Token, Box<Expression>),
     Literal(Literal),
     Grouping(Box<Expression>),
-    Variable(Token),
-    Assign(Token, Box<Expression>),
+    // The resolver fills in the number of scopes between the usage and the declaration,
+    // `None` means the variable is global.
+    Variable(Token, Cell<Option<usize>>),
+    Assign(Token, Box<Expression>, Cell<Option<usize>>),
     Logical(Box<Expression>, Token, Box<Expression>),
+    Call(Box<Expression>, Token, Vec<Expression>),
+    Get(Box<Expression>, Token),
+    Set(Box<Expression>, Token, Box<Expression>),
+    List(Vec<Expression>),
+    // Key and value expressions in source order.
+    Map(Vec<(Expression, Expression)>),
+    // Indexed expression, the closing bracket and the index.
+    Index(Box<Expression>, Token, Box<Expression>),
+    SetIndex(Box<Expression>, Token, Box<Expression>, Box<Expression>),
+    // Converts the value to a string the way `print` shows it, produced for the
+    // expressions interpolated into strings.
+    Stringify(Box<Expression>),
+    This(Token, Cell<Option<usize>>),
+    // `super` keyword and the method name following the dot.
+    Super(Token, Token, Cell<Option<usize>>),
 }
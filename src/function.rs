@@ -0,0 +1,146 @@
+use std::{fmt, rc::Rc};
+
+use crate::{
+    environment::Environment,
+    error::RuntimeErrorKind,
+    statement::FunctionDeclaration,
+    value::{List, Map, Number, Value},
+};
+
+pub struct Function {
+    pub declaration: Rc<FunctionDeclaration>,
+    pub closure: Rc<Environment>,
+    pub is_initializer: bool,
+}
+
+impl Function {
+    pub fn new(
+        declaration: Rc<FunctionDeclaration>,
+        closure: Rc<Environment>,
+        is_initializer: bool,
+    ) -> Function {
+        Function {
+            declaration,
+            closure,
+            is_initializer,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
+    pub fn arity(&self) -> usize {
+        self.declaration.params.len()
+    }
+
+    // Produces a method whose closure has `this` bound to the given instance.
+    pub fn bind(&self, instance: Value) -> Function {
+        let env = Environment::from(self.closure.clone());
+        env.define("this".to_string(), Some(instance));
+        Function::new(self.declaration.clone(), Rc::new(env), self.is_initializer)
+    }
+}
+
+impl fmt::Debug for Function {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<fn {}>", self.name())
+    }
+}
+
+pub type NativeResult = Result<Value, RuntimeErrorKind>;
+
+// Function implemented in Rust by the host, see `Interpreter::define_native`.
+pub struct NativeFunction {
+    pub name: String,
+    pub arity: usize,
+    pub function: Box<dyn Fn(&Args) -> NativeResult>,
+}
+
+impl NativeFunction {
+    pub fn new(
+        name: &str,
+        arity: usize,
+        function: impl Fn(&Args) -> NativeResult + 'static,
+    ) -> NativeFunction {
+        NativeFunction {
+            name: name.to_string(),
+            arity,
+            function: Box::new(function),
+        }
+    }
+
+    pub fn call(&self, args: &[Value]) -> NativeResult {
+        (self.function)(&Args { values: args })
+    }
+}
+
+impl fmt::Debug for NativeFunction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "<native fn {}>", self.name)
+    }
+}
+
+// Arguments of a native call. The typed getters fail with an error naming the
+// argument when it has another type. The arity is checked before the call, so
+// indices below it are always present.
+pub struct Args<'a> {
+    values: &'a [Value],
+}
+
+impl Args<'_> {
+    pub fn len(&self) -> usize {
+        self.values.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.values.is_empty()
+    }
+
+    pub fn value(&self, index: usize) -> &Value {
+        &self.values[index]
+    }
+
+    pub fn number(&self, index: usize) -> Result<Number, RuntimeErrorKind> {
+        match self.value(index) {
+            Value::Number(n) => Ok(*n),
+            other => Err(self.mismatch(index, "number", other)),
+        }
+    }
+
+    pub fn string(&self, index: usize) -> Result<&str, RuntimeErrorKind> {
+        match self.value(index) {
+            Value::String(s) => Ok(s),
+            other => Err(self.mismatch(index, "string", other)),
+        }
+    }
+
+    pub fn bool(&self, index: usize) -> Result<bool, RuntimeErrorKind> {
+        match self.value(index) {
+            Value::Bool(b) => Ok(*b),
+            other => Err(self.mismatch(index, "bool", other)),
+        }
+    }
+
+    pub fn list(&self, index: usize) -> Result<List, RuntimeErrorKind> {
+        match self.value(index) {
+            Value::List(list) => Ok(list.clone()),
+            other => Err(self.mismatch(index, "list", other)),
+        }
+    }
+
+    pub fn map(&self, index: usize) -> Result<Map, RuntimeErrorKind> {
+        match self.value(index) {
+            Value::Map(map) => Ok(map.clone()),
+            other => Err(self.mismatch(index, "map", other)),
+        }
+    }
+
+    fn mismatch(&self, index: usize, expected: &'static str, got: &Value) -> RuntimeErrorKind {
+        RuntimeErrorKind::ArgumentType {
+            position: index + 1,
+            expected,
+            got: got.type_name(),
+        }
+    }
+}
@@ -1,47 +1,136 @@
-use std::rc::Rc;
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    io::{BufRead, Write},
+    rc::Rc,
+};
 
 use crate::{
+    class::{Class, Instance},
+    collector::CycleCollector,
     environment::{self, Environment},
-    expression::Expression,
-    statement::Statement,
+    error::{RuntimeError, RuntimeErrorKind, TraceFrame},
+    expression::{Expression, ExpressionKind},
+    function::{Args, Function, NativeFunction, NativeResult},
+    map,
+    natives::{self, Io},
+    object::{GcConfig, HeapStats},
+    statement::{Statement, StatementKind},
     token::{Literal, Token, TokenType},
-    value::{Number, Value},
+    value::{self, format_number, Dialect, List, Number, Value, MAX_FRAMES},
 };
-use anyhow::{anyhow, Result};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
 
 pub struct Interpreter {
     env: Rc<environment::Environment>,
+    globals: Rc<environment::Environment>,
+    // Shared with the natives that print or read.
+    io: Rc<RefCell<Io>>,
+    dialect: Dialect,
+    // Lox functions and initializers currently being called.
+    depth: usize,
+    collector: CycleCollector,
+}
+
+// Outcome of executing a statement: either fall through to the next one, unwind to
+// the nearest function call with the returned value or to the innermost loop.
+enum Flow {
+    Normal,
+    Return(Value),
+    Break,
+    Continue,
 }
 
-// TODO: add more info to errors like line where it happend and so on
 impl Interpreter {
     pub fn new() -> Interpreter {
-        Interpreter {
-            env: Rc::new(Environment::new()),
-        }
+        Interpreter::with_streams(Io::default())
+    }
+
+    // Interpreter that prints to `output` and lets `input()` read lines from `input`.
+    pub fn with_io(output: impl Write + 'static, input: impl BufRead + 'static) -> Interpreter {
+        Interpreter::with_streams(Io::new(output, input))
     }
 
-    pub fn interpret_statements(
+    fn with_streams(io: Io) -> Interpreter {
+        let io = Rc::new(RefCell::new(io));
+        let globals = Rc::new(Environment::new());
+        let mut collector = CycleCollector::new();
+        collector.track_environment(&globals);
+        let mut interpreter = Interpreter {
+            env: globals.clone(),
+            globals,
+            io: io.clone(),
+            dialect: Dialect::default(),
+            depth: 0,
+            collector,
+        };
+        natives::define_core(&mut interpreter, io);
+        interpreter
+    }
+
+    // Makes a Rust closure callable from Lox as a global function. Arguments are
+    // checked against `arity` before the closure runs, e.g.
+    //
+    // interpreter.define_native("add", 2, |args| Ok((args.number(0)? + args.number(1)?).into()));
+    pub fn define_native(
         &mut self,
-        statements: Vec<Statement>,
-    ) -> Result<(), anyhow::Error> {
+        name: &str,
+        arity: usize,
+        function: impl Fn(&Args) -> NativeResult + 'static,
+    ) {
+        let native = NativeFunction::new(name, arity, function);
+        self.globals
+            .define(name.to_string(), Some(Value::Native(Rc::new(native))));
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    // Controls how often reference cycles are looked for, see `CycleCollector`.
+    pub fn set_gc(&mut self, config: GcConfig) {
+        self.collector.set_config(config);
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.collector.stats()
+    }
+
+    pub fn interpret_statements(&mut self, statements: Vec<Statement>) -> Result<()> {
         for stmt in statements {
-            self.execute(&stmt)?;
+            // The resolver rejects these, callers that skip it get an error pointing
+            // at the statement the return came out of.
+            if let Flow::Return(_) = self.execute(&stmt)? {
+                return Err(RuntimeError::at(
+                    stmt.span,
+                    RuntimeErrorKind::TopLevelReturn,
+                ));
+            }
         }
 
         Ok(())
     }
 
-    fn execute(&mut self, statement: &Statement) -> Result<(), anyhow::Error> {
-        match statement {
-            Statement::Expression(expr) => {
+    pub fn interpret_expression(&mut self, expression: &Expression) -> Result<Value> {
+        self.evaluate(expression)
+    }
+
+    fn execute(&mut self, statement: &Statement) -> Result<Flow> {
+        match &statement.kind {
+            StatementKind::Expression(expr) => {
                 self.evaluate(expr)?;
             }
-            Statement::Print(expr) => {
+            StatementKind::Print(expr) => {
                 let value = self.evaluate(expr)?;
-                println!("{}", stringify(value)?);
+                if let Err(err) = writeln!(self.io.borrow_mut().output, "{}", stringify(value)) {
+                    return Err(RuntimeError::at(
+                        statement.span,
+                        RuntimeErrorKind::Io(err.to_string()),
+                    ));
+                }
             }
-            Statement::Variable(token, initializer) => {
+            StatementKind::Variable(token, initializer) => {
                 let mut value: Option<Value> = None;
                 if initializer.is_some() {
                     value = Some(self.evaluate(initializer.as_ref().unwrap())?);
@@ -49,111 +138,356 @@ impl Interpreter {
 
                 self.env.define(token.lexeme.clone(), value);
             }
-            Statement::Block(list) => {
-                self.execute_block(list, Environment::from(self.env.clone()))?;
+            StatementKind::Block(list) => {
+                return self.execute_block(list, Environment::from(self.env.clone()));
+            }
+            StatementKind::If(cond, then_scope, else_scope) => {
+                let cond = self.evaluate(cond)?;
+                if self.dialect == Dialect::Legacy && !matches!(cond, Value::Bool(_)) {
+                    return Ok(Flow::Normal);
+                }
+
+                if is_truthy(&cond, self.dialect) {
+                    return self.execute(then_scope.as_ref());
+                } else if let Some(else_scope) = else_scope {
+                    return self.execute(else_scope.as_ref());
+                }
             }
-            Statement::If(cond, then_scope, else_scope) => {
-                if let Value::Bool(cond_res) = self.evaluate(cond)? {
-                    if cond_res {
-                        let _ = self.execute(then_scope.as_ref());
-                    } else if else_scope.is_some() {
-                        let _ = self.execute(else_scope.as_ref().unwrap().as_ref());
+            StatementKind::While(cond, body, increment) => {
+                while is_truthy(&self.evaluate(cond)?, self.dialect) {
+                    match self.execute(body)? {
+                        Flow::Return(value) => return Ok(Flow::Return(value)),
+                        Flow::Break => break,
+                        Flow::Normal | Flow::Continue => {}
+                    }
+                    if let Some(increment) = increment {
+                        self.evaluate(increment)?;
                     }
                 }
             }
-            Statement::While(cond, body) => {
-                while is_truthy(&self.evaluate(cond)?) {
-                    self.execute(body)?;
+            StatementKind::Function(declaration) => {
+                let function = Function::new(declaration.clone(), self.env.clone(), false);
+                self.env.define(
+                    declaration.name.lexeme.clone(),
+                    Some(Value::Function(Rc::new(function))),
+                );
+            }
+            StatementKind::Class(name, superclass, declarations) => {
+                let superclass = match superclass {
+                    Some(superclass) => match self.evaluate(superclass)? {
+                        Value::Class(class) => Some(class),
+                        _ => {
+                            let token = match &superclass.kind {
+                                ExpressionKind::Variable(token, _) => token,
+                                _ => name,
+                            };
+                            return Err(RuntimeError::new(
+                                token,
+                                RuntimeErrorKind::SuperclassMustBeClass,
+                            ));
+                        }
+                    },
+                    None => None,
+                };
+
+                self.env.define(name.lexeme.clone(), None);
+
+                // Methods of a subclass close over an extra scope holding `super`.
+                let enclosing = self.env.clone();
+                if let Some(ref superclass) = superclass {
+                    let env = Environment::from(self.env.clone());
+                    env.define("super".to_string(), Some(Value::Class(superclass.clone())));
+                    self.env = Rc::new(env);
+                }
+
+                let mut methods = HashMap::new();
+                for declaration in declarations {
+                    let is_initializer = declaration.name.lexeme == "init";
+                    let method =
+                        Function::new(declaration.clone(), self.env.clone(), is_initializer);
+                    methods.insert(declaration.name.lexeme.clone(), Rc::new(method));
                 }
+                self.env = enclosing;
+
+                let class = Class::new(name.lexeme.clone(), superclass, methods);
+                self.env
+                    .update(name.lexeme.clone(), Some(Value::Class(Rc::new(class))));
             }
+            StatementKind::Return(_, value) => {
+                let value = match value {
+                    Some(value) => self.evaluate(value)?,
+                    None => Value::Nil,
+                };
+                return Ok(Flow::Return(value));
+            }
+            StatementKind::Break(_) => return Ok(Flow::Break),
+            StatementKind::Continue(_) => return Ok(Flow::Continue),
         }
 
-        Ok(())
+        Ok(Flow::Normal)
     }
 
-    fn execute_block(
-        &mut self,
-        stmts: &Vec<Statement>,
-        env: Environment,
-    ) -> Result<(), anyhow::Error> {
-        let prev_env = self.env.clone();
-        self.env = Rc::new(env);
+    fn execute_block(&mut self, stmts: &[Statement], env: Environment) -> Result<Flow> {
+        let env = Rc::new(env);
+        self.collector.track_environment(&env);
+        let prev_env = std::mem::replace(&mut self.env, env);
+        let mut res = Ok(Flow::Normal);
         for stmt in stmts {
-            self.execute(stmt)?
+            res = self.execute(stmt);
+            if !matches!(res, Ok(Flow::Normal)) {
+                break;
+            }
         }
         self.env = prev_env;
-        Ok(())
+        res
     }
 
-    fn evaluate(&self, expr: &Expression) -> Result<Value> {
-        match expr {
-            Expression::Binary(lhs, op, rhs) => self.binary(lhs, rhs, &op.typ),
-            Expression::Unary(lexeme, rhs) => self.unary(lexeme, rhs),
-            Expression::Grouping(group) => self.evaluate(group),
-            Expression::Literal(lit) => literal(lit),
-            Expression::Variable(token) => {
-                if !self.env.contains(&token.lexeme) {
-                    return Err(anyhow!("Undefined variable '{}", &token.lexeme));
-                }
+    fn call(&mut self, callee: Value, paren: &Token, args: Vec<Value>) -> Result<Value> {
+        let arity = match callee {
+            Value::Function(ref function) => function.arity(),
+            Value::Class(ref class) => class.arity(),
+            Value::Native(ref native) => native.arity,
+            _ => return Err(RuntimeError::new(paren, RuntimeErrorKind::NotCallable)),
+        };
 
-                let value = self.env.get(&token.lexeme);
-                if let Some(value) = value {
-                    return Ok(value);
-                }
-                Err(anyhow!(
-                    "Usage of uninitialized variable '{}'",
-                    &token.lexeme
-                ))
+        if args.len() != arity {
+            return Err(RuntimeError::new(
+                paren,
+                RuntimeErrorKind::ArityMismatch {
+                    expected: arity,
+                    got: args.len(),
+                },
+            ));
+        }
+
+        // Errors of native functions point at the call itself.
+        if let Value::Native(native) = callee {
+            let value = native
+                .call(&args)
+                .map_err(|kind| RuntimeError::new(paren, kind))?;
+            self.collector.track_value(&value);
+            return Ok(value);
+        }
+
+        // The script itself counts as a frame, like on the VM.
+        if self.depth + 1 >= MAX_FRAMES {
+            return Err(RuntimeError::new(paren, RuntimeErrorKind::StackOverflow));
+        }
+        self.depth += 1;
+        let (name, res) = match callee {
+            Value::Function(function) => (
+                function.name().to_string(),
+                self.call_function(&function, args),
+            ),
+            Value::Class(class) => {
+                let instance = Value::Instance(Rc::new(Instance::new(class.clone())));
+                self.collector.track_value(&instance);
+                let res = match class.find_method("init") {
+                    Some(init) => self
+                        .call_function(&init.bind(instance.clone()), args)
+                        .map(|_| instance),
+                    None => Ok(instance),
+                };
+                (class.name.clone(), res)
             }
-            Expression::Assign(tok, expr) => {
-                if !self.env.contains(&tok.lexeme) {
-                    return Err(anyhow!("{} is not declared", &tok.lexeme));
-                }
+            _ => unreachable!(),
+        };
+        self.depth -= 1;
+
+        res.map_err(|mut err| {
+            err.trace.push(TraceFrame {
+                function: name,
+                line: paren.line(),
+            });
+            err
+        })
+    }
+
+    fn call_function(&mut self, function: &Function, args: Vec<Value>) -> Result<Value> {
+        let env = Environment::from(function.closure.clone());
+        for (param, arg) in function.declaration.params.iter().zip(args) {
+            env.define(param.lexeme.clone(), Some(arg));
+        }
+
+        let flow = self.execute_block(&function.declaration.body, env)?;
+        if function.is_initializer {
+            return Ok(function.closure.get_at(0, "this").unwrap_or(Value::Nil));
+        }
+
+        match flow {
+            Flow::Return(value) => Ok(value),
+            Flow::Normal => Ok(Value::Nil),
+            Flow::Break | Flow::Continue => unreachable!("the parser keeps them inside loops"),
+        }
+    }
 
-                let rhs = self.evaluate(expr);
-                if let Ok(rhs) = rhs {
-                    let is_updated = self.env.update(tok.lexeme.clone(), Some(rhs.clone()));
-                    assert!(is_updated);
-                    return Ok(rhs);
+    fn evaluate(&mut self, expr: &Expression) -> Result<Value> {
+        match &expr.kind {
+            ExpressionKind::Binary(lhs, op, rhs) => self.binary(lhs, rhs, op),
+            ExpressionKind::Unary(lexeme, rhs) => self.unary(lexeme, rhs),
+            ExpressionKind::Grouping(group) => self.evaluate(group),
+            ExpressionKind::Stringify(value) => Ok(Value::String(stringify(self.evaluate(value)?))),
+            ExpressionKind::Literal(lit) => Ok(literal(lit)),
+            ExpressionKind::Variable(token, depth) => self.look_up_variable(token, depth.get()),
+            ExpressionKind::Assign(tok, expr, depth) => {
+                let rhs = self.evaluate(expr)?;
+                let is_updated = match depth.get() {
+                    Some(depth) => self
+                        .env
+                        .update_at(depth, tok.lexeme.clone(), Some(rhs.clone())),
+                    None => self.globals.update(tok.lexeme.clone(), Some(rhs.clone())),
+                };
+                if !is_updated {
+                    return Err(RuntimeError::new(
+                        tok,
+                        RuntimeErrorKind::UndefinedVariable(tok.lexeme.clone()),
+                    ));
                 }
 
-                Err(anyhow!("{}", rhs.err().unwrap()))
+                Ok(rhs)
             }
-            Expression::Logical(lhs_ptr, op, rhs_ptr) => {
+            ExpressionKind::Logical(lhs_ptr, op, rhs_ptr) => {
                 let lhs = self.evaluate(lhs_ptr)?;
-                let is_left = is_truthy(&lhs);
+                let is_left = is_truthy(&lhs, self.dialect);
                 match op.typ {
                     TokenType::Or if is_left => Ok(lhs),
                     TokenType::And if !is_left => Ok(lhs),
                     _ => self.evaluate(rhs_ptr),
                 }
             }
+            ExpressionKind::Call(callee, paren, args) => {
+                let callee = self.evaluate(callee)?;
+                let mut values = Vec::with_capacity(args.len());
+                for arg in args {
+                    values.push(self.evaluate(arg)?);
+                }
+                self.call(callee, paren, values)
+            }
+            ExpressionKind::Get(object, name) => match self.evaluate(object)? {
+                Value::Instance(instance) => {
+                    Instance::get(&instance, &name.lexeme).ok_or_else(|| {
+                        RuntimeError::new(
+                            name,
+                            RuntimeErrorKind::UndefinedProperty(name.lexeme.clone()),
+                        )
+                    })
+                }
+                _ => Err(RuntimeError::new(
+                    name,
+                    RuntimeErrorKind::OnlyInstancesHaveProperties,
+                )),
+            },
+            ExpressionKind::Set(object, name, value) => {
+                let Value::Instance(instance) = self.evaluate(object)? else {
+                    return Err(RuntimeError::new(
+                        name,
+                        RuntimeErrorKind::OnlyInstancesHaveFields,
+                    ));
+                };
+                let value = self.evaluate(value)?;
+                instance.set(name.lexeme.clone(), value.clone());
+                Ok(value)
+            }
+            ExpressionKind::List(elements) => {
+                let mut values = Vec::with_capacity(elements.len());
+                for element in elements {
+                    values.push(self.evaluate(element)?);
+                }
+                let list = Value::List(Rc::new(RefCell::new(values)));
+                self.collector.track_value(&list);
+                Ok(list)
+            }
+            ExpressionKind::Map(entries) => {
+                // Every entry is evaluated before any key is checked, as in the VM.
+                let mut pairs = Vec::with_capacity(entries.len());
+                for (key, value) in entries {
+                    pairs.push((self.evaluate(key)?, self.evaluate(value)?));
+                }
+                let mut map = map::Map::new();
+                for (key, value) in pairs {
+                    let key = key
+                        .to_key()
+                        .map_err(|kind| RuntimeError::at(expr.span, kind))?;
+                    map.insert(key, value);
+                }
+                let map = Value::Map(Rc::new(RefCell::new(map)));
+                self.collector.track_value(&map);
+                Ok(map)
+            }
+            ExpressionKind::Index(object, bracket, index) => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                get_index(&object, &index).map_err(|kind| RuntimeError::new(bracket, kind))
+            }
+            ExpressionKind::SetIndex(object, bracket, index, value) => {
+                let object = self.evaluate(object)?;
+                let index = self.evaluate(index)?;
+                let value = self.evaluate(value)?;
+                set_index(&object, &index, value.clone())
+                    .map_err(|kind| RuntimeError::new(bracket, kind))?;
+                Ok(value)
+            }
+            ExpressionKind::This(keyword, depth) => self.look_up_variable(keyword, depth.get()),
+            ExpressionKind::Super(keyword, method, depth) => {
+                // The resolver guarantees `super` is bound in an enclosing scope and
+                // `this` lives in the scope right inside it.
+                let depth = depth.get().expect("'super' is resolved");
+                let Some(Value::Class(superclass)) = self.env.get_at(depth, &keyword.lexeme) else {
+                    unreachable!("'super' is bound to a class")
+                };
+                let instance = self
+                    .env
+                    .get_at(depth - 1, "this")
+                    .expect("'this' is bound in methods");
+
+                let method = superclass.find_method(&method.lexeme).ok_or_else(|| {
+                    RuntimeError::new(
+                        method,
+                        RuntimeErrorKind::UndefinedProperty(method.lexeme.clone()),
+                    )
+                })?;
+                Ok(Value::Function(Rc::new(method.bind(instance))))
+            }
         }
     }
 
-    fn binary(&self, lhs: &Expression, rhs: &Expression, typ: &TokenType) -> Result<Value> {
-        let left = self.evaluate(lhs);
-        let right = self.evaluate(rhs);
-
-        if let Some(left_err) = left.as_ref().err() {
-            return Err(anyhow!("{}", left_err));
-        }
+    fn look_up_variable(&self, name: &Token, depth: Option<usize>) -> Result<Value> {
+        let value = match depth {
+            Some(depth) => self.env.get_at(depth, &name.lexeme),
+            None => {
+                if !self.globals.contains(&name.lexeme) {
+                    return Err(RuntimeError::new(
+                        name,
+                        RuntimeErrorKind::UndefinedVariable(name.lexeme.clone()),
+                    ));
+                }
+                self.globals.get(&name.lexeme)
+            }
+        };
 
-        if let Some(right_err) = right.as_ref().err() {
-            return Err(anyhow!("{}", right_err));
-        }
+        value.ok_or_else(|| {
+            RuntimeError::new(
+                name,
+                RuntimeErrorKind::UninitializedVariable(name.lexeme.clone()),
+            )
+        })
+    }
 
-        let left = left.unwrap();
-        let right = right.unwrap();
+    fn binary(&mut self, lhs: &Expression, rhs: &Expression, op: &Token) -> Result<Value> {
+        let left = self.evaluate(lhs)?;
+        let right = self.evaluate(rhs)?;
 
-        let handle_num = |f: fn(Number, Number) -> Number, error: anyhow::Error| -> Result<Value> {
+        let handle_num = |f: fn(Number, Number) -> Number| -> Result<Value> {
             if let Value::Number(left) = left {
                 if let Value::Number(right) = right {
                     return Ok(Value::Number(f(left, right)));
                 }
             }
 
-            Err(error)
+            Err(RuntimeError::new(
+                op,
+                RuntimeErrorKind::OperandsMustBeNumbers,
+            ))
         };
 
         let handle_bool = |f: fn(Number, Number) -> bool| -> Result<Value> {
@@ -163,52 +497,52 @@ impl Interpreter {
                 }
             }
 
-            Err(anyhow!("Undefined op"))
+            Err(RuntimeError::new(
+                op,
+                RuntimeErrorKind::OperandsMustBeNumbers,
+            ))
         };
 
-        match typ {
-            TokenType::Minus => {
-                handle_num(|left, right| left - right, anyhow!("Failed to map minus"))
-            }
-            TokenType::Slash => handle_num(
-                |left, right| left / right,
-                anyhow!("Failed to map division"),
-            ),
-            TokenType::Star => handle_num(
-                |left, right| left * right,
-                anyhow!("Failed to map multiply"),
-            ),
+        match op.typ {
+            TokenType::Minus => handle_num(|left, right| left - right),
+            TokenType::Slash => handle_num(|left, right| left / right),
+            TokenType::Star => handle_num(|left, right| left * right),
             TokenType::Plus => {
                 if let Value::Number(_) = left {
-                    return handle_num(|left, right| left + right, anyhow!("Failed to map plus"));
+                    if let Value::Number(_) = right {
+                        return handle_num(|left, right| left + right);
+                    }
                 }
 
-                if let Value::String(left) = left {
-                    if let Value::String(right) = right {
-                        return Ok(Value::String(left + &right));
+                if let Value::String(ref left) = left {
+                    if let Value::String(ref right) = right {
+                        return Ok(Value::String(left.clone() + right));
                     }
                 }
 
-                Err(anyhow!("Failed to map plus operator"))
+                Err(RuntimeError::new(
+                    op,
+                    RuntimeErrorKind::OperandsMustBeNumbersOrStrings,
+                ))
             }
             TokenType::Greater => handle_bool(|left, right| left > right),
             TokenType::GreaterEqual => handle_bool(|left, right| left >= right),
             TokenType::Less => handle_bool(|left, right| left < right),
             TokenType::LessEqual => handle_bool(|left, right| left <= right),
-            TokenType::NotEqual => handle_bool(|left, right| left != right),
-            TokenType::EqualEqual => handle_bool(|left, right| left == right),
-            _ => todo!(),
+            TokenType::NotEqual if self.dialect == Dialect::Legacy => {
+                handle_bool(|left, right| left != right)
+            }
+            TokenType::EqualEqual if self.dialect == Dialect::Legacy => {
+                handle_bool(|left, right| left == right)
+            }
+            TokenType::NotEqual => Ok(Value::Bool(!is_equal(&left, &right))),
+            TokenType::EqualEqual => Ok(Value::Bool(is_equal(&left, &right))),
+            _ => unreachable!("Not a binary operator: {:?}", op.typ),
         }
     }
 
-    fn unary(&self, lexeme: &Token, rhs: &Expression) -> Result<Value> {
-        let right = self.evaluate(rhs);
-
-        if right.is_err() {
-            return Err(anyhow!("error"));
-        }
-
-        let right = right.unwrap();
+    fn unary(&mut self, lexeme: &Token, rhs: &Expression) -> Result<Value> {
+        let right = self.evaluate(rhs)?;
 
         match lexeme.typ {
             TokenType::Minus => {
@@ -216,16 +550,25 @@ impl Interpreter {
                     return Ok(Value::Number(-right));
                 }
 
-                Err(anyhow!("Not a number"))
+                Err(RuntimeError::new(
+                    lexeme,
+                    RuntimeErrorKind::OperandMustBeNumber,
+                ))
             }
             TokenType::Not => {
                 if let Value::Bool(right) = right {
                     return Ok(Value::Bool(!right));
                 }
+                if self.dialect == Dialect::Lox {
+                    return Ok(Value::Bool(!is_truthy(&right, self.dialect)));
+                }
 
-                Err(anyhow!("Not a boolean"))
+                Err(RuntimeError::new(
+                    lexeme,
+                    RuntimeErrorKind::OperandMustBeBoolean,
+                ))
             }
-            _ => todo!(),
+            _ => unreachable!("Not a unary operator: {:?}", lexeme.typ),
         }
     }
 }
@@ -236,30 +579,127 @@ impl Default for Interpreter {
     }
 }
 
-fn stringify(obj: Value) -> Result<String> {
+pub fn stringify(obj: Value) -> String {
     match obj {
-        Value::String(s) => Ok(s),
-        Value::Bool(b) => Ok(b.to_string()),
-        Value::Nil => Ok("nil".to_string()),
-        Value::Number(n) => Ok(n.to_string()),
+        Value::String(s) => s,
+        Value::Bool(b) => b.to_string(),
+        Value::Nil => "nil".to_string(),
+        Value::Number(n) => format_number(n),
+        Value::Function(f) => format!("<fn {}>", f.name()),
+        Value::Native(f) => format!("<native fn {}>", f.name),
+        Value::Class(c) => c.name.clone(),
+        Value::Instance(i) => format!("{} instance", i.class.name),
+        Value::List(_) | Value::Map(_) => stringify_nested(&obj, &mut vec![]),
     }
 }
 
-fn literal(lit: &Literal) -> Result<Value> {
+// A list or map nested in itself prints as `[...]` or `{...}` instead of recursing
+// forever. `enclosing` holds the containers currently being printed. Strings inside
+// them are quoted.
+fn stringify_nested(value: &Value, enclosing: &mut Vec<*const ()>) -> String {
+    let (pointer, open, close) = match value {
+        Value::List(list) => (Rc::as_ptr(list) as *const (), "[", "]"),
+        Value::Map(map) => (Rc::as_ptr(map) as *const (), "{", "}"),
+        Value::String(s) => return value::quote(s),
+        value => return stringify(value.clone()),
+    };
+    if enclosing.contains(&pointer) {
+        return format!("{}...{}", open, close);
+    }
+
+    enclosing.push(pointer);
+    let items: Vec<String> = match value {
+        Value::List(list) => list
+            .borrow()
+            .iter()
+            .map(|item| stringify_nested(item, enclosing))
+            .collect(),
+        Value::Map(map) => map
+            .borrow()
+            .iter()
+            .map(|(key, item)| format!("{}: {}", key.quoted(), stringify_nested(item, enclosing)))
+            .collect(),
+        _ => unreachable!(),
+    };
+    enclosing.pop();
+    format!("{}{}{}", open, items.join(", "), close)
+}
+
+fn get_index(object: &Value, index: &Value) -> std::result::Result<Value, RuntimeErrorKind> {
+    if let Value::Map(map) = object {
+        let key = index.to_key()?;
+        let value = map.borrow().get(&key).cloned();
+        return value.ok_or_else(|| RuntimeErrorKind::UndefinedKey(key.to_string()));
+    }
+
+    let (list, position) = list_element(object, index)?;
+    let value = list.borrow()[position].clone();
+    Ok(value)
+}
+
+fn set_index(
+    object: &Value,
+    index: &Value,
+    value: Value,
+) -> std::result::Result<(), RuntimeErrorKind> {
+    if let Value::Map(map) = object {
+        map.borrow_mut().insert(index.to_key()?, value);
+        return Ok(());
+    }
+
+    let (list, position) = list_element(object, index)?;
+    list.borrow_mut()[position] = value;
+    Ok(())
+}
+
+fn list_element(
+    object: &Value,
+    index: &Value,
+) -> std::result::Result<(List, usize), RuntimeErrorKind> {
+    let Value::List(list) = object else {
+        return Err(RuntimeErrorKind::NotIndexable);
+    };
+    let Value::Number(index) = index else {
+        return Err(RuntimeErrorKind::IndexMustBeInteger);
+    };
+    let position = value::list_index(*index, list.borrow().len(), false)?;
+    Ok((list.clone(), position))
+}
+
+fn literal(lit: &Literal) -> Value {
     match lit {
-        Literal::Number(n) => Ok(Value::Number(*n)),
-        Literal::String(s) => Ok(Value::String(s.clone())),
-        Literal::Bool(b) => Ok(Value::Bool(*b)),
-        Literal::Nil => Ok(Value::Nil),
+        Literal::Number(n) => Value::Number(*n),
+        Literal::String(s) => Value::String(s.clone()),
+        Literal::Bool(b) => Value::Bool(*b),
+        Literal::Nil => Value::Nil,
+    }
+}
+
+// Values of different types are never equal, functions, classes and instances are
+// equal only to themselves.
+fn is_equal(left: &Value, right: &Value) -> bool {
+    match (left, right) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Function(a), Value::Function(b)) => Rc::ptr_eq(a, b),
+        (Value::Native(a), Value::Native(b)) => Rc::ptr_eq(a, b),
+        (Value::Class(a), Value::Class(b)) => Rc::ptr_eq(a, b),
+        (Value::Instance(a), Value::Instance(b)) => Rc::ptr_eq(a, b),
+        (Value::List(a), Value::List(b)) => Rc::ptr_eq(a, b),
+        (Value::Map(a), Value::Map(b)) => Rc::ptr_eq(a, b),
+        _ => false,
     }
 }
 
-fn is_truthy(val: &Value) -> bool {
+fn is_truthy(val: &Value, dialect: Dialect) -> bool {
     match val {
         Value::Bool(b) => *b,
-        // TODO: probably need to compare delta with epsilon or use separate type for floats
-        Value::Number(n) => *n != 0.0,
-        Value::String(s) => !s.is_empty(),
         Value::Nil => false,
+        // TODO: probably need to compare delta with epsilon or use separate type for floats
+        Value::Number(n) if dialect == Dialect::Legacy => *n != 0.0,
+        Value::String(s) if dialect == Dialect::Legacy => !s.is_empty(),
+        _ => true,
     }
 }
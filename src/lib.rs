@@ -1,9 +1,22 @@
+pub mod chunk;
+pub mod class;
+pub mod collector;
+pub mod compiler;
+pub mod disassembler;
 pub mod environment;
 pub mod error;
 pub mod expression;
+pub mod function;
 pub mod interpreter;
+pub mod map;
+pub mod natives;
+pub mod object;
 pub mod parser;
+pub mod repl;
+pub mod resolver;
 pub mod scanner;
+pub mod serialize;
 pub mod statement;
 pub mod token;
 pub mod value;
+pub mod vm;
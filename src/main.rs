@@ -1,44 +1,233 @@
-use anyhow::Result;
+use std::path::Path;
+use std::rc::Rc;
+
+use jlox::chunk::FunctionProto;
+use jlox::compiler::Compiler;
+use jlox::disassembler;
+use jlox::error::{color_diagnostics, Diagnostic};
 use jlox::interpreter::Interpreter;
+use jlox::object::GcConfig;
 use jlox::parser::Parser;
+use jlox::repl::Repl;
+use jlox::resolver::Resolver;
 use jlox::scanner::Scanner;
+use jlox::serialize;
+use jlox::statement::Statement;
+use jlox::value::Dialect;
+use jlox::vm::Vm;
+
+const USAGE: &str = "Usage: jlox [--vm] [--dump-bytecode] [--gc-stress] [--gc-threshold <objects>]
+            [--dialect <lox|legacy>] [script]
+       jlox compile <script> [-o <output>]
+
+--dump-bytecode, --gc-stress and --gc-threshold run the script on the VM.";
+
+// Exit codes from sysexits.h.
+const EX_USAGE: i32 = 64;
+const EX_DATAERR: i32 = 65;
+const EX_NOINPUT: i32 = 66;
+const EX_SOFTWARE: i32 = 70;
+const EX_CANTCREAT: i32 = 73;
+const EX_IOERR: i32 = 74;
+
+#[derive(Default)]
+struct Options {
+    // Run on the bytecode VM instead of the tree-walking interpreter.
+    vm: bool,
+    // Print the compiled bytecode instead of running the script.
+    dump_bytecode: bool,
+    // Garbage collector settings of the VM, the flags imply `--vm`.
+    gc: GcConfig,
+    dialect: Dialect,
+    // Write the compiled script to a file instead of running it.
+    compile: bool,
+    output: Option<String>,
+    script: Option<String>,
+}
 
-fn run(source: Vec<char>) {
-    let mut scanner = Scanner::new(source);
-    scanner.scan();
-    scanner.log();
+// Scans, parses and resolves the source, reporting any diagnostics.
+fn parse(source: &str, color: bool) -> Option<Vec<Statement>> {
+    let mut scanner = Scanner::new(source.chars().collect());
+    if let Err(diagnostics) = scanner.scan() {
+        return report(source, &diagnostics, color);
+    }
     let tokens = scanner.tokens();
     let mut parser = Parser::new(tokens);
-    let stmts = parser.parse();
-    if stmts.is_err() {
-        println!("Parsing failed");
-        return;
+    let stmts = match parser.parse() {
+        Ok(stmts) => stmts,
+        Err(diagnostics) => return report(source, &diagnostics, color),
+    };
+
+    let mut resolver = Resolver::new();
+    if let Err(diagnostics) = resolver.resolve(&stmts) {
+        return report(source, &diagnostics, color);
+    }
+
+    Some(stmts)
+}
+
+fn compile(source: &str, color: bool) -> Option<Rc<FunctionProto>> {
+    let stmts = parse(source, color)?;
+    match Compiler::new().compile(&stmts) {
+        Ok(function) => Some(function),
+        Err(diagnostics) => report(source, &diagnostics, color),
+    }
+}
+
+// Failures are reported as they happen and leave the exit code to return.
+fn run(source: &str, options: &Options) -> Result<(), i32> {
+    let color = color_diagnostics();
+
+    if options.vm || options.dump_bytecode {
+        let function = compile(source, color).ok_or(EX_DATAERR)?;
+        return run_function(function, source, options);
     }
 
-    let stmts = stmts.ok().unwrap();
+    let stmts = parse(source, color).ok_or(EX_DATAERR)?;
     let mut interpreter = Interpreter::new();
-    let res = interpreter.interpret_statements(stmts);
-    if res.is_err() {
-        println!("{}", res.err().unwrap());
+    interpreter.set_dialect(options.dialect);
+    interpreter.interpret_statements(stmts).map_err(|err| {
+        eprint!("{}", err.render(source, color));
+        EX_SOFTWARE
+    })
+}
+
+fn run_function(function: Rc<FunctionProto>, source: &str, options: &Options) -> Result<(), i32> {
+    if options.dump_bytecode {
+        print!("{}", disassembler::disassemble(&function));
+        return Ok(());
     }
+
+    let mut vm = Vm::with_gc(options.gc);
+    vm.set_dialect(options.dialect);
+    vm.interpret(function).map_err(|err| {
+        eprint!("{}", err.render(source, color_diagnostics()));
+        EX_SOFTWARE
+    })
 }
 
-fn read_file(filename: &str) -> Result<String> {
-    Ok(std::fs::read_to_string(filename)?)
+// Compiled files always run on the VM. Their source isn't available, so errors are
+// reported by position only.
+fn run_bytecode(filename: &str, bytes: &[u8], options: &Options) -> Result<(), i32> {
+    match serialize::deserialize(bytes) {
+        Ok(function) => run_function(function, "", options),
+        Err(err) => {
+            eprintln!("error: {}: {}", filename, err);
+            Err(EX_DATAERR)
+        }
+    }
 }
 
-fn main() {
-    let args: Vec<String> = std::env::args().collect();
-
-    match args.len() {
-        2 => {
-            let filename = args[1].as_str();
-            let source = read_file(filename);
-            match source {
-                Ok(source) => run(source.chars().collect()),
-                Err(e) => panic!("Failed to open file {}: {}", filename, e),
+fn compile_file(source: &str, output: &str) -> Result<(), i32> {
+    let color = color_diagnostics();
+    let function = compile(source, color).ok_or(EX_DATAERR)?;
+
+    std::fs::write(output, serialize::serialize(&function)).map_err(|e| {
+        eprintln!("error: {}: {}", output, e);
+        EX_CANTCREAT
+    })
+}
+
+fn report<T>(source: &str, diagnostics: &[Diagnostic], color: bool) -> Option<T> {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(source, color));
+    }
+    None
+}
+
+fn parse_args(args: impl Iterator<Item = String>) -> Option<Options> {
+    let mut options = Options::default();
+    let mut args = args.peekable();
+    if args.next_if(|arg| arg == "compile").is_some() {
+        options.compile = true;
+    }
+
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--vm" if !options.compile => options.vm = true,
+            "--dump-bytecode" if !options.compile => options.dump_bytecode = true,
+            "--gc-stress" if !options.compile => {
+                options.vm = true;
+                options.gc.stress = true;
             }
+            "--gc-threshold" if !options.compile => {
+                options.vm = true;
+                options.gc.threshold = args.next()?.parse().ok().filter(|n| *n > 0)?;
+            }
+            "--dialect" if !options.compile => {
+                options.dialect = match args.next()?.as_str() {
+                    "lox" => Dialect::Lox,
+                    "legacy" => Dialect::Legacy,
+                    _ => return None,
+                };
+            }
+            "-o" if options.compile && options.output.is_none() => options.output = args.next(),
+            _ if arg.starts_with('-') => return None,
+            _ if options.script.is_none() => options.script = Some(arg),
+            _ => return None,
         }
-        _ => panic!("Wrong number of arguments"),
     }
+
+    if options.compile && options.script.is_none() {
+        return None;
+    }
+    Some(options)
+}
+
+// The tree-walking interpreter recurses on the native stack, and a debug build needs
+// tens of kilobytes per Lox call to get to `MAX_FRAMES`. Only the pages actually
+// touched are committed.
+const STACK_SIZE: usize = 256 * 1024 * 1024;
+
+fn main() {
+    let jlox = std::thread::Builder::new()
+        .stack_size(STACK_SIZE)
+        .spawn(run_cli)
+        .expect("Failed to spawn the main thread");
+    match jlox.join() {
+        Ok(Ok(())) => {}
+        Ok(Err(code)) => std::process::exit(code),
+        Err(_) => std::process::exit(101),
+    }
+}
+
+fn run_cli() -> Result<(), i32> {
+    let Some(options) = parse_args(std::env::args().skip(1)) else {
+        eprintln!("{}", USAGE);
+        return Err(EX_USAGE);
+    };
+
+    let Some(ref filename) = options.script else {
+        // The REPL always runs on the tree-walking interpreter.
+        let mut repl = Repl::new();
+        repl.set_dialect(options.dialect);
+        if let Err(e) = repl.run() {
+            eprintln!("error: {}", e);
+            return Err(EX_IOERR);
+        }
+        return Ok(());
+    };
+
+    let bytes = std::fs::read(filename).map_err(|e| {
+        eprintln!("error: {}: {}", filename, e);
+        EX_NOINPUT
+    })?;
+    if serialize::is_bytecode(&bytes) && !options.compile {
+        return run_bytecode(filename, &bytes, &options);
+    }
+
+    let source = String::from_utf8(bytes).map_err(|e| {
+        eprintln!("error: {}: {}", filename, e);
+        EX_NOINPUT
+    })?;
+    if options.compile {
+        let output = options.output.clone().unwrap_or_else(|| {
+            Path::new(filename)
+                .with_extension("loxc")
+                .to_string_lossy()
+                .into_owned()
+        });
+        return compile_file(&source, &output);
+    }
+    run(&source, &options)
 }
@@ -0,0 +1,119 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    error::RuntimeErrorKind,
+    value::{format_number, quote, Number},
+};
+
+// The subset of values that can be used as map keys. Numbers are kept as their
+// bits with `-0` folded into `0`, so keys that compare equal also hash the same.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Key {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(Rc<str>),
+}
+
+impl Key {
+    pub fn number(n: Number) -> Result<Key, RuntimeErrorKind> {
+        if n.is_nan() {
+            return Err(RuntimeErrorKind::NanKey);
+        }
+        let n = if n == 0.0 { 0.0 } else { n };
+        Ok(Key::Number(n.to_bits()))
+    }
+
+    // The key as printed inside a map, with strings quoted.
+    pub fn quoted(&self) -> String {
+        match self {
+            Key::String(s) => quote(s),
+            key => key.to_string(),
+        }
+    }
+}
+
+impl fmt::Display for Key {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Key::Nil => write!(f, "nil"),
+            Key::Bool(b) => write!(f, "{}", b),
+            Key::Number(bits) => write!(f, "{}", format_number(Number::from_bits(*bits))),
+            Key::String(s) => write!(f, "{}", s),
+        }
+    }
+}
+
+// Hash map that remembers insertion order, so printing and iterating a map gives the
+// same result on every run and on both backends. Shared by the interpreter and the
+// VM, which store their own value types in it.
+#[derive(Clone, Debug)]
+pub struct Map<V> {
+    entries: Vec<(Key, V)>,
+    positions: HashMap<Key, usize>,
+}
+
+impl<V> Map<V> {
+    pub fn new() -> Map<V> {
+        Map {
+            entries: vec![],
+            positions: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, key: &Key) -> Option<&V> {
+        self.positions.get(key).map(|&i| &self.entries[i].1)
+    }
+
+    pub fn contains(&self, key: &Key) -> bool {
+        self.positions.contains_key(key)
+    }
+
+    // Overwriting a key keeps its original position.
+    pub fn insert(&mut self, key: Key, value: V) -> Option<V> {
+        if let Some(&i) = self.positions.get(&key) {
+            return Some(std::mem::replace(&mut self.entries[i].1, value));
+        }
+        self.positions.insert(key.clone(), self.entries.len());
+        self.entries.push((key, value));
+        None
+    }
+
+    pub fn remove(&mut self, key: &Key) -> Option<V> {
+        let i = self.positions.remove(key)?;
+        let (_, value) = self.entries.remove(i);
+        for (key, _) in &self.entries[i..] {
+            *self
+                .positions
+                .get_mut(key)
+                .expect("every entry has a position") -= 1;
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Key, &V)> {
+        self.entries.iter().map(|(key, value)| (key, value))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Key> {
+        self.entries.iter().map(|(key, _)| key)
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &V> {
+        self.entries.iter().map(|(_, value)| value)
+    }
+}
+
+impl<V> Default for Map<V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
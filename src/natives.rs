@@ -0,0 +1,363 @@
+use std::{
+    cell::RefCell,
+    io::{self, BufRead, Write},
+    rc::Rc,
+    sync::OnceLock,
+    time::Instant,
+};
+
+use crate::{
+    error::RuntimeErrorKind,
+    interpreter::{self, Interpreter},
+    map::{Key, Map},
+    object::{self, Heap, Native, Object},
+    value::{list_index, Number, Value},
+};
+
+// Core library, defined as globals by both backends before a script runs. Each native
+// exists twice, once per value representation, the shared work lives in the helpers
+// at the bottom.
+
+// Streams a program prints to and reads from, shared by `print` and the natives of
+// both backends. Without an input source `input()` reads standard input, locking it
+// only while reading so idle interpreters don't block others.
+pub struct Io {
+    pub output: Box<dyn Write>,
+    pub input: Option<Box<dyn BufRead>>,
+}
+
+impl Io {
+    pub fn new(output: impl Write + 'static, input: impl BufRead + 'static) -> Io {
+        Io {
+            output: Box::new(output),
+            input: Some(Box::new(input)),
+        }
+    }
+
+    // Next line without its line ending, `None` at the end of input.
+    pub fn read_line(&mut self) -> Option<String> {
+        match &mut self.input {
+            Some(input) => read_line(input),
+            None => read_line(&mut io::stdin().lock()),
+        }
+    }
+}
+
+impl Default for Io {
+    fn default() -> Self {
+        Io {
+            output: Box::new(io::stdout()),
+            input: None,
+        }
+    }
+}
+
+// `exit` flushes the output before leaving.
+pub fn define_core(interpreter: &mut Interpreter, io: Rc<RefCell<Io>>) {
+    interpreter.define_native("clock", 0, |_| Ok(clock().into()));
+    interpreter.define_native("str", 1, |args| {
+        Ok(interpreter::stringify(args.value(0).clone()).into())
+    });
+    interpreter.define_native("num", 1, |args| match args.value(0) {
+        Value::Number(n) => Ok((*n).into()),
+        Value::String(s) => Ok(parse_number(s)?.into()),
+        other => Err(mismatch(1, "number or string", other.type_name())),
+    });
+    interpreter.define_native("type", 1, |args| Ok(args.value(0).type_name().into()));
+    interpreter.define_native("len", 1, |args| match args.value(0) {
+        Value::String(s) => Ok(length(s).into()),
+        Value::List(list) => Ok((list.borrow().len() as Number).into()),
+        Value::Map(map) => Ok((map.borrow().len() as Number).into()),
+        other => Err(mismatch(1, "string, list or map", other.type_name())),
+    });
+    interpreter.define_native("push", 2, |args| {
+        args.list(0)?.borrow_mut().push(args.value(1).clone());
+        Ok(Value::Nil)
+    });
+    interpreter.define_native("pop", 1, |args| pop(&mut args.list(0)?.borrow_mut()));
+    interpreter.define_native("insert", 3, |args| {
+        let list = args.list(0)?;
+        insert(
+            &mut list.borrow_mut(),
+            args.number(1)?,
+            args.value(2).clone(),
+        )?;
+        Ok(Value::Nil)
+    });
+    interpreter.define_native("remove", 2, |args| match args.value(0) {
+        Value::List(list) => remove(&mut list.borrow_mut(), args.number(1)?),
+        Value::Map(map) => remove_key(&mut map.borrow_mut(), args.value(1).to_key()?),
+        other => Err(mismatch(1, "list or map", other.type_name())),
+    });
+    interpreter.define_native("slice", 3, |args| {
+        let items = slice(&args.list(0)?.borrow(), args.number(1)?, args.number(2)?)?;
+        Ok(Value::List(Rc::new(RefCell::new(items))))
+    });
+    interpreter.define_native("has", 2, |args| {
+        Ok(args
+            .map(0)?
+            .borrow()
+            .contains(&args.value(1).to_key()?)
+            .into())
+    });
+    interpreter.define_native("keys", 1, |args| {
+        let keys = args.map(0)?.borrow().keys().map(Value::from).collect();
+        Ok(Value::List(Rc::new(RefCell::new(keys))))
+    });
+    interpreter.define_native("values", 1, |args| {
+        let values = args.map(0)?.borrow().values().cloned().collect();
+        Ok(Value::List(Rc::new(RefCell::new(values))))
+    });
+    let input = io.clone();
+    interpreter.define_native("input", 0, move |_| {
+        let line = input.borrow_mut().read_line();
+        Ok(line.map_or(Value::Nil, Value::from))
+    });
+    interpreter.define_native("exit", 1, move |args| {
+        let code = args.number(0)?;
+        let _ = io.borrow_mut().output.flush();
+        exit(code)
+    });
+}
+
+pub const VM_CORE: [Native; 15] = [
+    Native {
+        name: "clock",
+        arity: 0,
+        function: |_, _, _| Ok(object::Value::Number(clock())),
+    },
+    Native {
+        name: "str",
+        arity: 1,
+        function: |heap, _, args| Ok(object::Value::String(heap.stringify(&args[0]).into())),
+    },
+    Native {
+        name: "num",
+        arity: 1,
+        function: |heap, _, args| match &args[0] {
+            object::Value::Number(n) => Ok(object::Value::Number(*n)),
+            object::Value::String(s) => Ok(object::Value::Number(parse_number(s)?)),
+            other => Err(mismatch(1, "number or string", heap.type_name(other))),
+        },
+    },
+    Native {
+        name: "type",
+        arity: 1,
+        function: |heap, _, args| Ok(object::Value::String(heap.type_name(&args[0]).into())),
+    },
+    Native {
+        name: "len",
+        arity: 1,
+        function: |heap, _, args| match &args[0] {
+            object::Value::String(s) => Ok(object::Value::Number(length(s))),
+            object::Value::Object(obj) => match heap.get(*obj) {
+                Object::List(items) => Ok(object::Value::Number(items.len() as Number)),
+                Object::Map(map) => Ok(object::Value::Number(map.len() as Number)),
+                _ => Err(mismatch(1, "string, list or map", heap.type_name(&args[0]))),
+            },
+            other => Err(mismatch(1, "string, list or map", heap.type_name(other))),
+        },
+    },
+    Native {
+        name: "push",
+        arity: 2,
+        function: |heap, _, args| {
+            list_arg(heap, &args[0])?.push(args[1].clone());
+            Ok(object::Value::Nil)
+        },
+    },
+    Native {
+        name: "pop",
+        arity: 1,
+        function: |heap, _, args| pop(list_arg(heap, &args[0])?),
+    },
+    Native {
+        name: "insert",
+        arity: 3,
+        function: |heap, _, args| {
+            let index = number_arg(heap, args, 1)?;
+            insert(list_arg(heap, &args[0])?, index, args[2].clone())?;
+            Ok(object::Value::Nil)
+        },
+    },
+    Native {
+        name: "remove",
+        arity: 2,
+        function: |heap, _, args| match &args[0] {
+            object::Value::Object(obj) if matches!(heap.get(*obj), Object::Map(_)) => {
+                let key = heap.key(&args[1])?;
+                remove_key(heap.map_mut(*obj), key)
+            }
+            object::Value::Object(obj) if matches!(heap.get(*obj), Object::List(_)) => {
+                let index = number_arg(heap, args, 1)?;
+                remove(heap.list_mut(*obj), index)
+            }
+            other => Err(mismatch(1, "list or map", heap.type_name(other))),
+        },
+    },
+    Native {
+        name: "slice",
+        arity: 3,
+        function: |heap, _, args| {
+            let (start, end) = (number_arg(heap, args, 1)?, number_arg(heap, args, 2)?);
+            let items = slice(list_arg(heap, &args[0])?, start, end)?;
+            Ok(object::Value::Object(heap.alloc(Object::List(items))))
+        },
+    },
+    Native {
+        name: "has",
+        arity: 2,
+        function: |heap, _, args| {
+            let key = heap.key(&args[1])?;
+            Ok(object::Value::Bool(map_arg(heap, &args[0])?.contains(&key)))
+        },
+    },
+    Native {
+        name: "keys",
+        arity: 1,
+        function: |heap, _, args| {
+            let keys = map_arg(heap, &args[0])?
+                .keys()
+                .map(object::Value::from)
+                .collect();
+            Ok(object::Value::Object(heap.alloc(Object::List(keys))))
+        },
+    },
+    Native {
+        name: "values",
+        arity: 1,
+        function: |heap, _, args| {
+            let values = map_arg(heap, &args[0])?.values().cloned().collect();
+            Ok(object::Value::Object(heap.alloc(Object::List(values))))
+        },
+    },
+    Native {
+        name: "input",
+        arity: 0,
+        function: |_, io, _| {
+            Ok(io.read_line().map_or(object::Value::Nil, |line| {
+                object::Value::String(line.into())
+            }))
+        },
+    },
+    Native {
+        name: "exit",
+        arity: 1,
+        function: |heap, io, args| match &args[0] {
+            object::Value::Number(code) => {
+                let _ = io.output.flush();
+                exit(*code)
+            }
+            other => Err(mismatch(1, "number", heap.type_name(other))),
+        },
+    },
+];
+
+fn mismatch(position: usize, expected: &'static str, got: &'static str) -> RuntimeErrorKind {
+    RuntimeErrorKind::ArgumentType {
+        position,
+        expected,
+        got,
+    }
+}
+
+// Lists are always the first argument of the natives working on them.
+fn list_arg<'a>(
+    heap: &'a mut Heap,
+    value: &object::Value,
+) -> Result<&'a mut Vec<object::Value>, RuntimeErrorKind> {
+    match value {
+        object::Value::Object(obj) if matches!(heap.get(*obj), Object::List(_)) => {
+            Ok(heap.list_mut(*obj))
+        }
+        other => Err(mismatch(1, "list", heap.type_name(other))),
+    }
+}
+
+// Maps are always the first argument of the natives working on them.
+fn map_arg<'a>(
+    heap: &'a mut Heap,
+    value: &object::Value,
+) -> Result<&'a mut Map<object::Value>, RuntimeErrorKind> {
+    match value {
+        object::Value::Object(obj) if matches!(heap.get(*obj), Object::Map(_)) => {
+            Ok(heap.map_mut(*obj))
+        }
+        other => Err(mismatch(1, "map", heap.type_name(other))),
+    }
+}
+
+fn number_arg(
+    heap: &Heap,
+    args: &[object::Value],
+    index: usize,
+) -> Result<Number, RuntimeErrorKind> {
+    match &args[index] {
+        object::Value::Number(n) => Ok(*n),
+        other => Err(mismatch(index + 1, "number", heap.type_name(other))),
+    }
+}
+
+// Seconds since the first call, small enough to keep sub-millisecond precision.
+fn clock() -> Number {
+    static START: OnceLock<Instant> = OnceLock::new();
+    START.get_or_init(Instant::now).elapsed().as_secs_f64() as Number
+}
+
+// Rust also parses "inf" and "nan", and overflows to infinity, none of which Lox
+// number literals can produce.
+fn parse_number(s: &str) -> Result<Number, RuntimeErrorKind> {
+    s.trim()
+        .parse()
+        .ok()
+        .filter(|n: &Number| n.is_finite())
+        .ok_or_else(|| RuntimeErrorKind::Native(format!("Can't convert '{}' to a number", s)))
+}
+
+fn length(s: &str) -> Number {
+    s.chars().count() as Number
+}
+
+fn pop<T>(list: &mut Vec<T>) -> Result<T, RuntimeErrorKind> {
+    list.pop()
+        .ok_or_else(|| RuntimeErrorKind::Native("Can't pop from an empty list".to_string()))
+}
+
+fn insert<T>(list: &mut Vec<T>, index: Number, item: T) -> Result<(), RuntimeErrorKind> {
+    let position = list_index(index, list.len(), true)?;
+    list.insert(position, item);
+    Ok(())
+}
+
+fn remove<T>(list: &mut Vec<T>, index: Number) -> Result<T, RuntimeErrorKind> {
+    let position = list_index(index, list.len(), false)?;
+    Ok(list.remove(position))
+}
+
+fn remove_key<T>(map: &mut Map<T>, key: Key) -> Result<T, RuntimeErrorKind> {
+    map.remove(&key)
+        .ok_or_else(|| RuntimeErrorKind::UndefinedKey(key.to_string()))
+}
+
+// Elements from `start` up to but not including `end`, empty when `end` comes first.
+fn slice<T: Clone>(list: &[T], start: Number, end: Number) -> Result<Vec<T>, RuntimeErrorKind> {
+    let start = list_index(start, list.len(), true)?;
+    let end = list_index(end, list.len(), true)?;
+    Ok(list[start..end.max(start)].to_vec())
+}
+
+fn read_line(input: &mut dyn BufRead) -> Option<String> {
+    let mut line = String::new();
+    match input.read_line(&mut line) {
+        Ok(0) | Err(_) => None,
+        Ok(_) => {
+            let len = line.trim_end_matches(['\n', '\r']).len();
+            line.truncate(len);
+            Some(line)
+        }
+    }
+}
+
+fn exit<T>(code: Number) -> Result<T, RuntimeErrorKind> {
+    std::process::exit(code as i32)
+}
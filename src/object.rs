@@ -0,0 +1,379 @@
+use std::{collections::HashMap, fmt, rc::Rc};
+
+use crate::{
+    chunk::FunctionProto,
+    error::RuntimeErrorKind,
+    map::{Key, Map},
+    natives::Io,
+    value::{format_number, quote, Number},
+};
+
+// Runtime value of the bytecode VM. Strings are immutable and shared, everything that
+// can be mutated or captured lives on the `Heap` and is referred to by handle.
+#[derive(Clone, Debug)]
+pub enum Value {
+    Bool(bool),
+    Number(Number),
+    String(Rc<str>),
+    Object(ObjRef),
+    Nil,
+    // Stored in variables declared without an initializer, never visible to programs.
+    Uninitialized,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ObjRef(u32);
+
+pub enum Object {
+    Closure(Closure),
+    Upvalue(Upvalue),
+    Class(Class),
+    Instance(Instance),
+    BoundMethod(BoundMethod),
+    Native(Native),
+    List(Vec<Value>),
+    Map(Map<Value>),
+}
+
+pub struct Closure {
+    pub function: Rc<FunctionProto>,
+    pub upvalues: Vec<ObjRef>,
+}
+
+// Variable captured by a closure. It points into the VM stack while the declaring
+// function is running and owns the value once that slot goes away.
+pub enum Upvalue {
+    Open(usize),
+    Closed(Value),
+}
+
+pub struct Class {
+    pub name: Rc<str>,
+    // Inherited methods are copied in when the class is created, so lookups never
+    // have to walk the superclass chain.
+    pub methods: HashMap<Rc<str>, ObjRef>,
+}
+
+pub struct Instance {
+    pub class: ObjRef,
+    pub fields: HashMap<Rc<str>, Value>,
+}
+
+pub struct BoundMethod {
+    pub receiver: Value,
+    pub method: ObjRef,
+}
+
+// Function implemented in Rust, see `natives::VM_CORE`. Objects it allocates skip the
+// collection check, the next allocation by the VM catches up.
+#[derive(Clone, Copy)]
+pub struct Native {
+    pub name: &'static str,
+    pub arity: usize,
+    pub function: fn(&mut Heap, &mut Io, &[Value]) -> Result<Value, RuntimeErrorKind>,
+}
+
+// Collect once this many objects are alive, unless configured otherwise.
+pub const DEFAULT_GC_THRESHOLD: usize = 1024;
+// After a collection the threshold becomes the surviving objects times this factor.
+pub const GC_GROWTH_FACTOR: usize = 2;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct GcConfig {
+    // Number of live objects that triggers the first collection.
+    pub threshold: usize,
+    // Collect before every allocation, to shake out missing roots.
+    pub stress: bool,
+}
+
+impl Default for GcConfig {
+    fn default() -> Self {
+        GcConfig {
+            threshold: DEFAULT_GC_THRESHOLD,
+            stress: false,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct HeapStats {
+    pub live: usize,
+    pub collections: usize,
+    pub freed: usize,
+}
+
+// Storage for VM objects, reclaimed by a mark-and-sweep collector. The heap doesn't
+// know the roots: the VM marks them and then calls `collect`.
+#[derive(Default)]
+pub struct Heap {
+    objects: Vec<Option<Object>>,
+    marks: Vec<bool>,
+    // Slots of swept objects, reused by later allocations.
+    free: Vec<u32>,
+    // Marked objects whose references haven't been traced yet.
+    gray: Vec<ObjRef>,
+    config: GcConfig,
+    next_gc: usize,
+    stats: HeapStats,
+}
+
+impl Heap {
+    pub fn new() -> Heap {
+        Heap::with_config(GcConfig::default())
+    }
+
+    pub fn with_config(config: GcConfig) -> Heap {
+        Heap {
+            config,
+            next_gc: config.threshold,
+            ..Heap::default()
+        }
+    }
+
+    pub fn alloc(&mut self, object: Object) -> ObjRef {
+        self.stats.live += 1;
+        if let Some(index) = self.free.pop() {
+            self.objects[index as usize] = Some(object);
+            return ObjRef(index);
+        }
+
+        self.objects.push(Some(object));
+        self.marks.push(false);
+        ObjRef(self.objects.len() as u32 - 1)
+    }
+
+    pub fn should_collect(&self) -> bool {
+        self.config.stress || self.stats.live >= self.next_gc
+    }
+
+    pub fn stats(&self) -> HeapStats {
+        self.stats
+    }
+
+    pub fn mark(&mut self, obj: ObjRef) {
+        mark(&mut self.marks, &mut self.gray, obj);
+    }
+
+    pub fn mark_value(&mut self, value: &Value) {
+        if let Some(obj) = as_object(value) {
+            self.mark(obj);
+        }
+    }
+
+    // Marks what an object refers to without the object itself, for objects that are
+    // about to be allocated.
+    pub fn mark_references(&mut self, object: &Object) {
+        references(object, |obj| mark(&mut self.marks, &mut self.gray, obj));
+    }
+
+    // Traces everything reachable from the marked roots and frees the rest.
+    pub fn collect(&mut self) {
+        while let Some(obj) = self.gray.pop() {
+            let Heap {
+                objects,
+                marks,
+                gray,
+                ..
+            } = self;
+            let object = objects[obj.0 as usize].as_ref().expect("object is alive");
+            references(object, |child| mark(marks, gray, child));
+        }
+
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            if !std::mem::take(&mut self.marks[index]) && object.take().is_some() {
+                self.free.push(index as u32);
+                self.stats.live -= 1;
+                self.stats.freed += 1;
+            }
+        }
+
+        self.stats.collections += 1;
+        self.next_gc = (self.stats.live * GC_GROWTH_FACTOR).max(self.config.threshold);
+    }
+
+    pub fn get(&self, obj: ObjRef) -> &Object {
+        self.objects[obj.0 as usize]
+            .as_ref()
+            .expect("object is alive")
+    }
+
+    pub fn get_mut(&mut self, obj: ObjRef) -> &mut Object {
+        self.objects[obj.0 as usize]
+            .as_mut()
+            .expect("object is alive")
+    }
+
+    pub fn closure(&self, obj: ObjRef) -> &Closure {
+        match self.get(obj) {
+            Object::Closure(closure) => closure,
+            _ => unreachable!("expected a closure"),
+        }
+    }
+
+    pub fn upvalue_mut(&mut self, obj: ObjRef) -> &mut Upvalue {
+        match self.get_mut(obj) {
+            Object::Upvalue(upvalue) => upvalue,
+            _ => unreachable!("expected an upvalue"),
+        }
+    }
+
+    pub fn class(&self, obj: ObjRef) -> &Class {
+        match self.get(obj) {
+            Object::Class(class) => class,
+            _ => unreachable!("expected a class"),
+        }
+    }
+
+    pub fn list_mut(&mut self, obj: ObjRef) -> &mut Vec<Value> {
+        match self.get_mut(obj) {
+            Object::List(items) => items,
+            _ => unreachable!("expected a list"),
+        }
+    }
+
+    pub fn map_mut(&mut self, obj: ObjRef) -> &mut Map<Value> {
+        match self.get_mut(obj) {
+            Object::Map(map) => map,
+            _ => unreachable!("expected a map"),
+        }
+    }
+
+    pub fn class_mut(&mut self, obj: ObjRef) -> &mut Class {
+        match self.get_mut(obj) {
+            Object::Class(class) => class,
+            _ => unreachable!("expected a class"),
+        }
+    }
+
+    pub fn stringify(&self, value: &Value) -> String {
+        match value {
+            Value::String(s) => s.to_string(),
+            value => self.stringify_nested(value, &mut vec![]),
+        }
+    }
+
+    // `enclosing` holds the lists and maps being printed, one nested in itself prints
+    // as `[...]` or `{...}` instead of recursing forever. Strings inside them are
+    // quoted.
+    fn stringify_nested(&self, value: &Value, enclosing: &mut Vec<ObjRef>) -> String {
+        match value {
+            Value::String(s) => quote(s),
+            Value::Bool(b) => b.to_string(),
+            Value::Nil | Value::Uninitialized => "nil".to_string(),
+            Value::Number(n) => format_number(*n),
+            Value::Object(obj) => match self.get(*obj) {
+                Object::Closure(closure) => closure.function.to_string(),
+                Object::Upvalue(_) => "upvalue".to_string(),
+                Object::Class(class) => class.name.to_string(),
+                Object::Instance(instance) => {
+                    format!("{} instance", self.class(instance.class).name)
+                }
+                Object::BoundMethod(bound) => self.closure(bound.method).function.to_string(),
+                Object::Native(native) => format!("<native fn {}>", native.name),
+                Object::List(_) if enclosing.contains(obj) => "[...]".to_string(),
+                Object::List(items) => {
+                    enclosing.push(*obj);
+                    let items: Vec<String> = items
+                        .iter()
+                        .map(|item| self.stringify_nested(item, enclosing))
+                        .collect();
+                    enclosing.pop();
+                    format!("[{}]", items.join(", "))
+                }
+                Object::Map(_) if enclosing.contains(obj) => "{...}".to_string(),
+                Object::Map(map) => {
+                    enclosing.push(*obj);
+                    let entries: Vec<String> = map
+                        .iter()
+                        .map(|(key, item)| {
+                            let item = self.stringify_nested(item, enclosing);
+                            format!("{}: {}", key.quoted(), item)
+                        })
+                        .collect();
+                    enclosing.pop();
+                    format!("{{{}}}", entries.join(", "))
+                }
+            },
+        }
+    }
+
+    pub fn type_name(&self, value: &Value) -> &'static str {
+        match value {
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Nil | Value::Uninitialized => "nil",
+            Value::Object(obj) => match self.get(*obj) {
+                Object::Closure(_) | Object::BoundMethod(_) | Object::Native(_) => "function",
+                Object::Class(_) => "class",
+                Object::Instance(_) => "instance",
+                Object::List(_) => "list",
+                Object::Map(_) => "map",
+                Object::Upvalue(_) => "upvalue",
+            },
+        }
+    }
+
+    pub fn key(&self, value: &Value) -> Result<Key, RuntimeErrorKind> {
+        match value {
+            Value::Nil => Ok(Key::Nil),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Number(n) => Key::number(*n),
+            Value::String(s) => Ok(Key::String(s.clone())),
+            value => Err(RuntimeErrorKind::UnhashableKey(self.type_name(value))),
+        }
+    }
+}
+
+impl fmt::Debug for Heap {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Heap({} objects)", self.stats.live)
+    }
+}
+
+fn mark(marks: &mut [bool], gray: &mut Vec<ObjRef>, obj: ObjRef) {
+    let marked = &mut marks[obj.0 as usize];
+    if !*marked {
+        *marked = true;
+        gray.push(obj);
+    }
+}
+
+// Calls `f` with every object directly referenced by `object`.
+fn references(object: &Object, mut f: impl FnMut(ObjRef)) {
+    match object {
+        Object::Closure(closure) => closure.upvalues.iter().copied().for_each(f),
+        Object::Upvalue(Upvalue::Closed(value)) => as_object(value).into_iter().for_each(f),
+        Object::Upvalue(Upvalue::Open(_)) | Object::Native(_) => {}
+        Object::Class(class) => class.methods.values().copied().for_each(f),
+        Object::Instance(instance) => {
+            f(instance.class);
+            instance.fields.values().filter_map(as_object).for_each(f);
+        }
+        Object::BoundMethod(bound) => {
+            as_object(&bound.receiver).into_iter().for_each(&mut f);
+            f(bound.method);
+        }
+        Object::List(items) => items.iter().filter_map(as_object).for_each(f),
+        // Keys are never objects.
+        Object::Map(map) => map.values().filter_map(as_object).for_each(f),
+    }
+}
+
+impl From<&Key> for Value {
+    fn from(key: &Key) -> Value {
+        match key {
+            Key::Nil => Value::Nil,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Number(bits) => Value::Number(Number::from_bits(*bits)),
+            Key::String(s) => Value::String(s.clone()),
+        }
+    }
+}
+
+fn as_object(value: &Value) -> Option<ObjRef> {
+    match value {
+        Value::Object(obj) => Some(*obj),
+        _ => None,
+    }
+}
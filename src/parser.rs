@@ -1,29 +1,48 @@
+use std::{cell::Cell, rc::Rc};
+
 use crate::{
-    error::Error,
-    expression::Expression,
-    statement::Statement,
-    token::{Literal, Token, TokenType},
+    error::Diagnostic,
+    expression::{Expression, ExpressionKind},
+    statement::{FunctionDeclaration, Statement, StatementKind},
+    token::{Literal, Span, Token, TokenType},
 };
 
 pub struct Parser {
     tokens: Vec<Token>,
     current: usize,
-    error: Error,
+    diagnostics: Vec<Diagnostic>,
+    // Number of loops around the statement being parsed, within the current function.
+    loop_depth: usize,
 }
 
 #[derive(Debug)]
 pub struct ParseError;
 
+const MAX_ARGUMENTS: usize = 255;
+
 // Grammar:
 //
 // program        → declaration* EOF ;
-// declaration    → varDecl
+// declaration    → classDecl
+//                | funDecl
+//                | varDecl
 //                | statement ;
+// classDecl      → "class" IDENTIFIER ( "<" IDENTIFIER )?
+//                  "{" function* "}" ;
+// funDecl        → "fun" function ;
+// function       → IDENTIFIER "(" parameters? ")" block ;
+// parameters     → IDENTIFIER ( "," IDENTIFIER )* ;
 // varDecl        → "var" IDENTIFIER ( "=" expression )? ";" ;
 // statement      → exprStmt
 //                | printStmt
+//                | returnStmt
+//                | breakStmt
+//                | continueStmt
 //                | block
 //                | if_statement ;
+// returnStmt     → "return" expression? ";" ;
+// breakStmt      → "break" ";" ;
+// continueStmt   → "continue" ";" ;
 // forStmt        → "for" "(" ( varDecl | exprStmt | ";" )
 //                  expression? ";"
 //                  expression? ")" statement ;
@@ -34,7 +53,8 @@ pub struct ParseError;
 // if_statement   → "if" "(" expression ")" statement
 //                   ( "else" statement )? ;
 // expression     → assignment ;
-// assignment     → logic_or | IDENTIFIER "=" assignment ;
+// assignment     → ( ( call "." )? IDENTIFIER | call "[" expression "]" ) "=" assignment
+//                | logic_or ;
 // logic_or       → logic_and ( "or" logic_and )* ;
 // logic_and      → equality ( "and" equality )* ;
 // equality       → comparison ( ( "!=" | "==" ) comparison )* ;
@@ -42,40 +62,72 @@ pub struct ParseError;
 // term           → factor ( ( "-" | "+" ) factor )* ;
 // factor         → unary ( ( "/" | "*" ) unary )* ;
 // unary          → ( "!" | "-" ) unary
-//                | primary ;
-// primary        → NUMBER | STRING | "true" | "false" | "nil"
-//                | "(" expression ")" | IDENTIFIER;
+//                | call ;
+// call           → primary ( "(" arguments? ")" | "." IDENTIFIER | "[" expression "]" )* ;
+// arguments      → expression ( "," expression )* ;
+// primary        → NUMBER | STRING | interpolation | "true" | "false" | "nil" | "this"
+//                | "(" expression ")" | IDENTIFIER
+//                | "super" "." IDENTIFIER
+//                | "[" ( expression ( "," expression )* ","? )? "]"
+//                | "{" ( entry ( "," entry )* ","? )? "}" ;
+// entry          → expression ":" expression ;
+// interpolation  → ( INTERPOLATION expression )+ STRING ;
 
 impl Parser {
     pub fn new(tokens: Vec<Token>) -> Parser {
         Parser {
             tokens,
             current: 0,
-            error: Error::new(),
+            diagnostics: vec![],
+            loop_depth: 0,
         }
     }
 
-    // TODO: maybe return list or errors and the caller will output them if needed and etc?
-    pub fn parse(&mut self) -> Result<Vec<Statement>, ParseError> {
+    // Parses the whole program, recovering after errors so that every problem in the
+    // source is reported at once.
+    pub fn parse(&mut self) -> Result<Vec<Statement>, Vec<Diagnostic>> {
         let mut statements = vec![];
 
         while !self.is_at_end() {
-            let decl = self.declaration();
-            if decl.is_err() {
-                self.sync();
-                continue;
+            match self.declaration() {
+                Ok(decl) => statements.push(decl),
+                Err(_) => self.sync(),
             }
-            statements.push(decl.unwrap());
         }
 
-        if self.error.had_error {
-            return Err(ParseError);
+        if self.diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(std::mem::take(&mut self.diagnostics));
         }
 
         Ok(statements)
     }
 
+    // Parses input that has to be a single expression from start to end, such as a
+    // line typed at the REPL without its ';'.
+    pub fn parse_expression(&mut self) -> Result<Expression, Vec<Diagnostic>> {
+        if let Ok(expr) = self.expression() {
+            if self.is_at_end() && self.diagnostics.is_empty() {
+                return Ok(expr);
+            }
+            if self.diagnostics.is_empty() {
+                self.report_error(self.peek().clone(), "Expected end of expression");
+            }
+        }
+
+        Err(std::mem::take(&mut self.diagnostics))
+    }
+
     fn declaration(&mut self) -> Result<Statement, ParseError> {
+        if self.match_token(&[TokenType::Class]) {
+            return self.class_declaration();
+        }
+
+        if self.match_token(&[TokenType::Fun]) {
+            let start = self.previous().span;
+            let function = self.function("function")?;
+            return Ok(self.finish(start, StatementKind::Function(function)));
+        }
+
         if self.match_token(&[TokenType::Var]) {
             return self.var_decl();
         }
@@ -83,7 +135,76 @@ impl Parser {
         self.statement()
     }
 
+    fn class_declaration(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
+        let name = self.consume(TokenType::Identifier, "Expected class name")?;
+
+        let mut superclass = None;
+        if self.match_token(&[TokenType::Less]) {
+            let name = self.consume(TokenType::Identifier, "Expected superclass name")?;
+            let span = name.span;
+            superclass = Some(Expression::new(
+                ExpressionKind::Variable(name, Cell::new(None)),
+                span,
+            ));
+        }
+
+        self.consume(TokenType::LeftBrace, "Expected '{' before class body")?;
+
+        let mut methods = vec![];
+        while !self.check_token(&TokenType::RightBrace) && !self.is_at_end() {
+            methods.push(self.function("method")?);
+        }
+        self.consume(TokenType::RightBrace, "Expected '}' after class body")?;
+
+        Ok(self.finish(start, StatementKind::Class(name, superclass, methods)))
+    }
+
+    fn function(&mut self, kind: &str) -> Result<Rc<FunctionDeclaration>, ParseError> {
+        let name = self.consume(TokenType::Identifier, &format!("Expected {} name", kind))?;
+        self.consume(
+            TokenType::LeftParenthesis,
+            &format!("Expected '(' after {} name", kind),
+        )?;
+
+        let mut params = vec![];
+        if !self.check_token(&TokenType::RightParenthesis) {
+            loop {
+                if params.len() >= MAX_ARGUMENTS {
+                    self.report_error(
+                        self.peek().clone(),
+                        &format!("Can't have more than {} parameters", MAX_ARGUMENTS),
+                    );
+                }
+                params.push(self.consume(TokenType::Identifier, "Expected parameter name")?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+        self.consume(TokenType::RightParenthesis, "Expected ')' after parameters")?;
+
+        self.consume(
+            TokenType::LeftBrace,
+            &format!("Expected '{{' before {} body", kind),
+        )?;
+        // Loops around the declaration can't be left from inside the body.
+        let enclosing_loops = std::mem::take(&mut self.loop_depth);
+        let body = self.block_statements();
+        self.loop_depth = enclosing_loops;
+        let body = body?;
+        let span = name.span.to(self.previous().span);
+
+        Ok(Rc::new(FunctionDeclaration {
+            name,
+            params,
+            body,
+            span,
+        }))
+    }
+
     fn var_decl(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
         let name = self.consume(TokenType::Identifier, "Expected variable name")?;
         let mut expr = None;
         if self.match_token(&[TokenType::Equal]) {
@@ -94,7 +215,7 @@ impl Parser {
             "Expected ';' after variable declaration",
         );
 
-        Ok(Statement::Variable(name, expr))
+        Ok(self.finish(start, StatementKind::Variable(name, expr)))
     }
 
     fn statement(&mut self) -> Result<Statement, ParseError> {
@@ -102,7 +223,15 @@ impl Parser {
             return self.print_statement();
         }
 
-        if self.match_token(&[TokenType::LeftBrace]) {
+        if self.match_token(&[TokenType::Return]) {
+            return self.return_statement();
+        }
+
+        if self.match_token(&[TokenType::Break, TokenType::Continue]) {
+            return self.loop_jump();
+        }
+
+        if !self.starts_map() && self.match_token(&[TokenType::LeftBrace]) {
             return self.block();
         }
 
@@ -121,24 +250,53 @@ impl Parser {
         self.expression_statement()
     }
 
+    // A `{` at the start of a statement opens a block unless the token after the first
+    // one is a ':', as in `{"key": value}`. No statement can start that way, so map
+    // literals with a single-token first key can be used as expression statements.
+    fn starts_map(&self) -> bool {
+        self.check_token(&TokenType::LeftBrace)
+            && self
+                .tokens
+                .get(self.current + 2)
+                .is_some_and(|token| token.typ == TokenType::Colon)
+    }
+
     fn print_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after value")?;
-        Ok(Statement::Print(expr))
+        Ok(self.finish(start, StatementKind::Print(expr)))
+    }
+
+    fn return_statement(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.previous();
+        let start = keyword.span;
+        let mut value = None;
+        if !self.check_token(&TokenType::Semicolon) {
+            value = Some(self.expression()?);
+        }
+        self.consume(TokenType::Semicolon, "Expected ';' after return value")?;
+        Ok(self.finish(start, StatementKind::Return(keyword, value)))
     }
 
     fn block(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
+        let stmts = self.block_statements()?;
+        Ok(self.finish(start, StatementKind::Block(stmts)))
+    }
+
+    fn block_statements(&mut self) -> Result<Vec<Statement>, ParseError> {
         let mut stmts = vec![];
-        while !self.check_token(&TokenType::RightBrace) {
-            // TODO: what if declaration is erroneous?
-            stmts.push(self.declaration().unwrap());
+        while !self.check_token(&TokenType::RightBrace) && !self.is_at_end() {
+            stmts.push(self.declaration()?);
         }
 
         let _ = self.consume(TokenType::RightBrace, "Expected '}' after block");
-        Ok(Statement::Block(stmts))
+        Ok(stmts)
     }
 
     fn if_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
         let _ = self.consume(TokenType::LeftParenthesis, "Expected '(' after 'if'");
         let cond = self.expression()?;
         let _ = self.consume(TokenType::RightParenthesis, "Expected ')' after 'if'");
@@ -149,18 +307,50 @@ impl Parser {
             else_branch = Some(Box::new(self.statement()?))
         }
 
-        Ok(Statement::If(cond, then_branch, else_branch))
+        Ok(self.finish(start, StatementKind::If(cond, then_branch, else_branch)))
+    }
+
+    // `break` or `continue`, reported but still parsed when there is no loop to leave.
+    fn loop_jump(&mut self) -> Result<Statement, ParseError> {
+        let keyword = self.previous();
+        if self.loop_depth == 0 {
+            let message = format!("Can't use '{}' outside of a loop", keyword.lexeme);
+            self.report_error(keyword.clone(), &message);
+        }
+        self.consume(
+            TokenType::Semicolon,
+            &format!("Expected ';' after '{}'", keyword.lexeme),
+        )?;
+
+        let start = keyword.span;
+        let kind = match keyword.typ {
+            TokenType::Break => StatementKind::Break(keyword),
+            _ => StatementKind::Continue(keyword),
+        };
+        Ok(self.finish(start, kind))
     }
 
     fn while_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
         self.consume(TokenType::LeftParenthesis, "Expected '(' after 'while'.")?;
         let cond = self.expression()?;
         self.consume(TokenType::RightParenthesis, "Expected ')' after 'while'.")?;
-        let body = self.statement()?;
-        Ok(Statement::While(cond, Box::new(body)))
+        let body = self.loop_body()?;
+        Ok(self.finish(start, StatementKind::While(cond, Box::new(body), None)))
     }
 
+    fn loop_body(&mut self) -> Result<Statement, ParseError> {
+        self.loop_depth += 1;
+        let body = self.statement();
+        self.loop_depth -= 1;
+        body
+    }
+
+    // Desugars into a `while` loop, the synthesized nodes share the span of the whole
+    // `for` statement. The increment stays separate from the body so that `continue`
+    // still runs it.
     fn for_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.previous().span;
         self.consume(TokenType::LeftParenthesis, "Expected '(' after 'for'.")?;
         let init = if self.match_token(&[TokenType::Semicolon]) {
             None
@@ -185,28 +375,26 @@ impl Parser {
             "Expected ')' after for clauses.",
         )?;
 
-        let mut body = self.statement()?;
-        if let Some(inc) = inc {
-            body = Statement::Block(vec![body, Statement::Expression(inc)]);
-        }
-
-        if cond.is_none() {
-            cond = Some(Expression::Literal(Literal::Bool(true)));
-        }
+        let body = self.loop_body()?;
+        let span = start.to(self.previous().span);
+        let cond = cond.unwrap_or_else(|| {
+            Expression::new(ExpressionKind::Literal(Literal::Bool(true)), start)
+        });
 
-        body = Statement::While(cond.unwrap(), Box::new(body));
+        let mut body = Statement::new(StatementKind::While(cond, Box::new(body), inc), span);
 
         if let Some(init) = init {
-            body = Statement::Block(vec![init, body]);
+            body = Statement::new(StatementKind::Block(vec![init, body]), span);
         }
 
         Ok(body)
     }
 
     fn expression_statement(&mut self) -> Result<Statement, ParseError> {
+        let start = self.peek().span;
         let expr = self.expression()?;
         self.consume(TokenType::Semicolon, "Expected ';' after value")?;
-        Ok(Statement::Expression(expr))
+        Ok(self.finish(start, StatementKind::Expression(expr)))
     }
 
     fn expression(&mut self) -> Result<Expression, ParseError> {
@@ -218,14 +406,41 @@ impl Parser {
         if !self.match_token(&[TokenType::Equal]) {
             return expr;
         }
-
-        let lhs = expr;
-        if let Expression::Variable(tok) = lhs? {
-            let rhs = self.assignment()?;
-            return Ok(Expression::Assign(tok, Box::new(rhs)));
+        let equals = self.previous();
+
+        let target = expr?;
+        let span = target.span;
+        match target.kind {
+            ExpressionKind::Variable(tok, _) => {
+                let rhs = self.assignment()?;
+                let span = span.to(rhs.span);
+                return Ok(Expression::new(
+                    ExpressionKind::Assign(tok, Box::new(rhs), Cell::new(None)),
+                    span,
+                ));
+            }
+            ExpressionKind::Get(object, name) => {
+                let rhs = self.assignment()?;
+                let span = span.to(rhs.span);
+                return Ok(Expression::new(
+                    ExpressionKind::Set(object, name, Box::new(rhs)),
+                    span,
+                ));
+            }
+            ExpressionKind::Index(object, bracket, index) => {
+                let rhs = self.assignment()?;
+                let span = span.to(rhs.span);
+                return Ok(Expression::new(
+                    ExpressionKind::SetIndex(object, bracket, index, Box::new(rhs)),
+                    span,
+                ));
+            }
+            _ => {}
         }
 
-        Err(self.report_error(self.peek().clone(), "Failed to match assignment"))
+        let diagnostic = Diagnostic::error(&equals, "Invalid assignment target")
+            .with_help("only variables, fields, list elements and map entries can be assigned to");
+        Err(self.report(diagnostic))
     }
 
     fn logic_or(&mut self) -> Result<Expression, ParseError> {
@@ -233,7 +448,11 @@ impl Parser {
         while self.match_token(&[TokenType::Or]) {
             let op = self.previous();
             let rhs = self.logic_and()?;
-            expr = Expression::Logical(Box::new(expr), op, Box::new(rhs));
+            let span = expr.span.to(rhs.span);
+            expr = Expression::new(
+                ExpressionKind::Logical(Box::new(expr), op, Box::new(rhs)),
+                span,
+            );
         }
 
         Ok(expr)
@@ -244,31 +463,29 @@ impl Parser {
         while self.match_token(&[TokenType::And]) {
             let op = self.previous();
             let rhs = self.equality()?;
-            expr = Expression::Logical(Box::new(expr), op, Box::new(rhs));
+            let span = expr.span.to(rhs.span);
+            expr = Expression::new(
+                ExpressionKind::Logical(Box::new(expr), op, Box::new(rhs)),
+                span,
+            );
         }
 
         Ok(expr)
     }
 
     fn equality(&mut self) -> Result<Expression, ParseError> {
-        let mut expr = self.comparison();
+        let mut expr = self.comparison()?;
         while self.match_token(&[TokenType::NotEqual, TokenType::EqualEqual]) {
             let op = self.previous();
-            let right = self.comparison();
-            if right.is_err() || expr.is_err() {
-                todo!()
-            }
-            expr = Ok(Expression::Binary(
-                Box::new(expr.unwrap()),
-                op,
-                Box::new(right.unwrap()),
-            ));
+            let right = self.comparison()?;
+            expr = binary(expr, op, right);
         }
-        expr
+
+        Ok(expr)
     }
 
     fn comparison(&mut self) -> Result<Expression, ParseError> {
-        let mut expr = self.term();
+        let mut expr = self.term()?;
         while self.match_token(&[
             TokenType::Greater,
             TokenType::GreaterEqual,
@@ -276,108 +493,253 @@ impl Parser {
             TokenType::LessEqual,
         ]) {
             let op = self.previous();
-            let right = self.term();
-            if expr.is_err() || right.is_err() {
-                todo!()
-            }
-            expr = Ok(Expression::Binary(
-                Box::new(expr.unwrap()),
-                op,
-                Box::new(right.unwrap()),
-            ));
+            let right = self.term()?;
+            expr = binary(expr, op, right);
         }
 
-        expr
+        Ok(expr)
     }
 
     fn term(&mut self) -> Result<Expression, ParseError> {
-        let mut expr = self.factor();
+        let mut expr = self.factor()?;
         while self.match_token(&[TokenType::Minus, TokenType::Plus]) {
             let op = self.previous();
-            let right = self.factor();
-            if expr.is_err() {
-                return Err(expr.err().unwrap());
-            }
-            if right.is_err() {
-                return Err(right.err().unwrap());
-            }
-            expr = Ok(Expression::Binary(
-                Box::new(expr.unwrap()),
-                op,
-                Box::new(right.unwrap()),
-            ));
+            let right = self.factor()?;
+            expr = binary(expr, op, right);
         }
 
-        expr
+        Ok(expr)
     }
 
     fn factor(&mut self) -> Result<Expression, ParseError> {
-        let mut expr = self.unary();
+        let mut expr = self.unary()?;
         while self.match_token(&[TokenType::Slash, TokenType::Star]) {
             let op = self.previous();
-            let right = self.unary();
-            if right.is_err() || expr.is_err() {
-                todo!()
-            }
-            expr = Ok(Expression::Binary(
-                Box::new(expr.unwrap()),
-                op,
-                Box::new(right.unwrap()),
-            ));
+            let right = self.unary()?;
+            expr = binary(expr, op, right);
         }
 
-        expr
+        Ok(expr)
     }
 
     fn unary(&mut self) -> Result<Expression, ParseError> {
         if self.match_token(&[TokenType::Not, TokenType::Minus]) {
             let op = self.previous();
-            let right = self.unary();
-            if right.is_err() {
-                return Err(self.report_error(self.peek().clone(), "Failed to match rhs value"));
+            let right = self.unary()?;
+            let span = op.span.to(right.span);
+            return Ok(Expression::new(
+                ExpressionKind::Unary(op, Box::new(right)),
+                span,
+            ));
+        }
+
+        self.call()
+    }
+
+    fn call(&mut self) -> Result<Expression, ParseError> {
+        let mut expr = self.primary()?;
+        loop {
+            if self.match_token(&[TokenType::LeftParenthesis]) {
+                expr = self.finish_call(expr)?;
+            } else if self.match_token(&[TokenType::Dot]) {
+                let name =
+                    self.consume(TokenType::Identifier, "Expected property name after '.'")?;
+                let span = expr.span.to(name.span);
+                expr = Expression::new(ExpressionKind::Get(Box::new(expr), name), span);
+            } else if self.match_token(&[TokenType::LeftBracket]) {
+                let index = self.expression()?;
+                let bracket = self.consume(TokenType::RightBracket, "Expected ']' after index")?;
+                let span = expr.span.to(bracket.span);
+                expr = Expression::new(
+                    ExpressionKind::Index(Box::new(expr), bracket, Box::new(index)),
+                    span,
+                );
+            } else {
+                break;
             }
-            return Ok(Expression::Unary(op, Box::new(right.unwrap())));
         }
 
-        self.primary()
+        Ok(expr)
+    }
+
+    fn finish_call(&mut self, callee: Expression) -> Result<Expression, ParseError> {
+        let mut args = vec![];
+        if !self.check_token(&TokenType::RightParenthesis) {
+            loop {
+                if args.len() >= MAX_ARGUMENTS {
+                    self.report_error(
+                        self.peek().clone(),
+                        &format!("Can't have more than {} arguments", MAX_ARGUMENTS),
+                    );
+                }
+                args.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+        }
+
+        let paren = self.consume(TokenType::RightParenthesis, "Expected ')' after arguments")?;
+        let span = callee.span.to(paren.span);
+        Ok(Expression::new(
+            ExpressionKind::Call(Box::new(callee), paren, args),
+            span,
+        ))
     }
 
     fn primary(&mut self) -> Result<Expression, ParseError> {
         if self.match_token(&[TokenType::False]) {
-            let literal = Literal::Bool(false);
-            return Ok(Expression::Literal(literal));
+            return Ok(self.literal(Literal::Bool(false)));
         }
         if self.match_token(&[TokenType::True]) {
-            let literal = Literal::Bool(true);
-            return Ok(Expression::Literal(literal));
+            return Ok(self.literal(Literal::Bool(true)));
         }
         if self.match_token(&[TokenType::Nil]) {
-            let literal = Literal::Nil;
-            return Ok(Expression::Literal(literal));
+            return Ok(self.literal(Literal::Nil));
         }
 
         if self.match_token(&[TokenType::Number, TokenType::String]) {
-            return Ok(Expression::Literal(
-                self.previous().literal.clone().unwrap(),
+            let literal = self.previous().literal.clone().unwrap();
+            return Ok(self.literal(literal));
+        }
+
+        if self.match_token(&[TokenType::Interpolation]) {
+            return self.interpolation();
+        }
+
+        if self.match_token(&[TokenType::Super]) {
+            let keyword = self.previous();
+            self.consume(TokenType::Dot, "Expected '.' after 'super'")?;
+            let method = self.consume(TokenType::Identifier, "Expected superclass method name")?;
+            let span = keyword.span.to(method.span);
+            return Ok(Expression::new(
+                ExpressionKind::Super(keyword, method, Cell::new(None)),
+                span,
+            ));
+        }
+
+        if self.match_token(&[TokenType::This]) {
+            let keyword = self.previous();
+            let span = keyword.span;
+            return Ok(Expression::new(
+                ExpressionKind::This(keyword, Cell::new(None)),
+                span,
             ));
         }
 
         if self.match_token(&[TokenType::Identifier]) {
-            return Ok(Expression::Variable(self.previous()));
+            let name = self.previous();
+            let span = name.span;
+            return Ok(Expression::new(
+                ExpressionKind::Variable(name, Cell::new(None)),
+                span,
+            ));
         }
 
-        if self.match_token(&[TokenType::LeftParenthesis]) {
-            let expr = self.expression();
-            if expr.is_err() {
-                todo!()
+        if self.match_token(&[TokenType::LeftBracket]) {
+            let start = self.previous().span;
+            let mut elements = vec![];
+            // A trailing comma is allowed.
+            while !self.check_token(&TokenType::RightBracket) {
+                elements.push(self.expression()?);
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
             }
+            self.consume(TokenType::RightBracket, "Expected ']' after list elements")?;
+            return Ok(Expression::new(
+                ExpressionKind::List(elements),
+                start.to(self.previous().span),
+            ));
+        }
+
+        if self.match_token(&[TokenType::LeftBrace]) {
+            let start = self.previous().span;
+            let mut entries = vec![];
+            while !self.check_token(&TokenType::RightBrace) {
+                let key = self.expression()?;
+                self.consume(TokenType::Colon, "Expected ':' after map key")?;
+                entries.push((key, self.expression()?));
+                if !self.match_token(&[TokenType::Comma]) {
+                    break;
+                }
+            }
+            self.consume(TokenType::RightBrace, "Expected '}' after map entries")?;
+            return Ok(Expression::new(
+                ExpressionKind::Map(entries),
+                start.to(self.previous().span),
+            ));
+        }
+
+        if self.match_token(&[TokenType::LeftParenthesis]) {
+            let start = self.previous().span;
+            let expr = self.expression()?;
             let _ = self.consume(TokenType::RightParenthesis, "Expect ')' after expression");
-            return Ok(Expression::Grouping(Box::new(expr.unwrap())));
+            let span = start.to(self.previous().span);
+            return Ok(Expression::new(
+                ExpressionKind::Grouping(Box::new(expr)),
+                span,
+            ));
         }
 
         Err(self.report_error(self.peek().clone(), "Expected expression"))
     }
 
+    // Lowers `"a ${b} c"` into `"a " + b + " c"`, with every interpolated value
+    // converted to a string first. Empty parts of the literal are left out.
+    fn interpolation(&mut self) -> Result<Expression, ParseError> {
+        let mut parts = vec![self.string_part()];
+        loop {
+            let value = self.expression()?;
+            let span = value.span;
+            parts.push(Expression::new(
+                ExpressionKind::Stringify(Box::new(value)),
+                span,
+            ));
+
+            if !self.match_token(&[TokenType::Interpolation]) {
+                break;
+            }
+            parts.push(self.string_part());
+        }
+        self.consume(
+            TokenType::String,
+            "Expected '}' after interpolated expression",
+        )?;
+        parts.push(self.string_part());
+
+        let mut parts = parts.into_iter().filter(|part| !is_empty_string(part));
+        let mut expr = parts.next().expect("an interpolated expression");
+        for part in parts {
+            let plus = Token {
+                typ: TokenType::Plus,
+                lexeme: "+".to_string(),
+                literal: None,
+                span: part.span,
+            };
+            expr = binary(expr, plus, part);
+        }
+        Ok(expr)
+    }
+
+    fn string_part(&self) -> Expression {
+        self.literal(
+            self.previous()
+                .literal
+                .clone()
+                .expect("strings carry their text"),
+        )
+    }
+
+    fn literal(&self, literal: Literal) -> Expression {
+        Expression::new(ExpressionKind::Literal(literal), self.previous().span)
+    }
+
+    // Wraps a statement that began at `start` and ends at the last consumed token.
+    fn finish(&self, start: Span, kind: StatementKind) -> Statement {
+        Statement::new(kind, start.to(self.previous().span))
+    }
+
     fn match_token(&mut self, token_types: &[TokenType]) -> bool {
         let matched = token_types.iter().any(|token| self.check_token(token));
         if matched {
@@ -422,9 +784,11 @@ impl Parser {
     }
 
     fn report_error(&mut self, token: Token, message: &str) -> ParseError {
-        self.error.error(token, message);
-        self.error.had_error = true;
+        self.report(Diagnostic::error(&token, message))
+    }
 
+    fn report(&mut self, diagnostic: Diagnostic) -> ParseError {
+        self.diagnostics.push(diagnostic);
         ParseError
     }
 
@@ -444,7 +808,9 @@ impl Parser {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Break
+                | TokenType::Continue => return,
                 _ => {}
             }
 
@@ -452,3 +818,15 @@ impl Parser {
         }
     }
 }
+
+fn is_empty_string(expr: &Expression) -> bool {
+    matches!(&expr.kind, ExpressionKind::Literal(Literal::String(s)) if s.is_empty())
+}
+
+fn binary(left: Expression, op: Token, right: Expression) -> Expression {
+    let span = left.span.to(right.span);
+    Expression::new(
+        ExpressionKind::Binary(Box::new(left), op, Box::new(right)),
+        span,
+    )
+}
@@ -0,0 +1,258 @@
+use std::{
+    fs::{self, OpenOptions},
+    io::{self, IsTerminal, Write},
+    path::PathBuf,
+};
+
+use crate::{
+    error::{color_diagnostics, Diagnostic},
+    interpreter::{self, Interpreter},
+    parser::Parser,
+    resolver::Resolver,
+    scanner::Scanner,
+    statement::{Statement, StatementKind},
+    token::{Token, TokenType},
+    value::{Dialect, Value},
+};
+
+const PROMPT: &str = "> ";
+const CONTINUATION_PROMPT: &str = "... ";
+const HISTORY_FILE: &str = ".jlox_history";
+// Older entries are dropped from the history file once it holds more than this.
+const MAX_HISTORY: usize = 1000;
+
+// Interactive session that keeps a single interpreter alive so that declarations
+// survive between inputs.
+pub struct Repl {
+    interpreter: Interpreter,
+    history: Vec<String>,
+    history_path: Option<PathBuf>,
+    // Prompts are only shown when a person is typing, not for piped input.
+    interactive: bool,
+}
+
+impl Repl {
+    pub fn new() -> Repl {
+        let history_path =
+            std::env::var_os("HOME").map(|home| PathBuf::from(home).join(HISTORY_FILE));
+        let mut history: Vec<String> = history_path
+            .as_ref()
+            .and_then(|path| fs::read_to_string(path).ok())
+            .map(|history| history.lines().map(str::to_string).collect())
+            .unwrap_or_default();
+        history.drain(..history.len().saturating_sub(MAX_HISTORY));
+
+        Repl {
+            interpreter: Interpreter::new(),
+            history,
+            history_path,
+            interactive: io::stdin().is_terminal(),
+        }
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.interpreter.set_dialect(dialect);
+    }
+
+    pub fn run(&mut self) -> io::Result<()> {
+        // Stdin is locked per line only, `input()` in the evaluated code reads it too.
+        let mut lines = std::iter::from_fn(|| {
+            let mut line = String::new();
+            match io::stdin().read_line(&mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    let len = line.trim_end_matches(['\n', '\r']).len();
+                    line.truncate(len);
+                    Some(Ok(line))
+                }
+                Err(err) => Some(Err(err)),
+            }
+        });
+
+        while let Some(input) = self.read_input(&mut lines)? {
+            if input.trim().is_empty() {
+                continue;
+            }
+
+            if input.trim() == ":history" {
+                self.print_history();
+                continue;
+            }
+
+            self.add_history(&input);
+            self.eval(&input);
+        }
+
+        Ok(())
+    }
+
+    // Reads lines until every opened brace and parenthesis is closed so that blocks,
+    // functions and classes can span several lines. Returns None on end of input.
+    fn read_input(
+        &self,
+        lines: &mut impl Iterator<Item = io::Result<String>>,
+    ) -> io::Result<Option<String>> {
+        let mut input = String::new();
+        let mut prompt = PROMPT;
+
+        loop {
+            if self.interactive {
+                print!("{}", prompt);
+                io::stdout().flush()?;
+            }
+
+            let Some(line) = lines.next() else {
+                if self.interactive {
+                    println!();
+                }
+                return Ok((!input.is_empty()).then_some(input));
+            };
+
+            input.push_str(&line?);
+            input.push('\n');
+
+            if nesting_depth(&input) <= 0 {
+                return Ok(Some(input));
+            }
+            prompt = CONTINUATION_PROMPT;
+        }
+    }
+
+    fn eval(&mut self, source: &str) {
+        let color = color_diagnostics();
+        let stmts = match parse_input(source) {
+            Ok(stmts) => stmts,
+            Err(diagnostics) => return report(source, &diagnostics, color),
+        };
+
+        let mut resolver = Resolver::new();
+        if let Err(diagnostics) = resolver.resolve(&stmts) {
+            return report(source, &diagnostics, color);
+        }
+
+        for stmt in stmts {
+            let res = match stmt.kind {
+                // Bare expressions echo their value the way most REPLs do.
+                StatementKind::Expression(expr) => {
+                    self.interpreter.interpret_expression(&expr).map(|value| {
+                        if !matches!(value, Value::Nil) {
+                            println!("{}", interpreter::stringify(value));
+                        }
+                    })
+                }
+                kind => {
+                    let stmt = Statement::new(kind, stmt.span);
+                    self.interpreter.interpret_statements(vec![stmt])
+                }
+            };
+
+            if let Err(err) = res {
+                eprint!("{}", err.render(source, color));
+                return;
+            }
+        }
+    }
+
+    fn add_history(&mut self, input: &str) {
+        let entry = input.trim_end().replace('\n', " ");
+        if self.history.last() == Some(&entry) {
+            return;
+        }
+
+        self.history.push(entry);
+        let Some(path) = &self.history_path else {
+            return;
+        };
+
+        if self.history.len() > MAX_HISTORY {
+            self.history.drain(..self.history.len() - MAX_HISTORY);
+            let mut contents = self.history.join("\n");
+            contents.push('\n');
+            let _ = fs::write(path, contents);
+        } else if let Ok(mut file) = OpenOptions::new().create(true).append(true).open(path) {
+            let _ = writeln!(file, "{}", self.history[self.history.len() - 1]);
+        }
+    }
+
+    fn print_history(&self) {
+        for (i, entry) in self.history.iter().enumerate() {
+            println!("{:>4}  {}", i + 1, entry);
+        }
+    }
+}
+
+impl Default for Repl {
+    fn default() -> Self {
+        Repl::new()
+    }
+}
+
+fn report(source: &str, diagnostics: &[Diagnostic], color: bool) {
+    for diagnostic in diagnostics {
+        eprint!("{}", diagnostic.render(source, color));
+    }
+}
+
+// Parses one REPL input. Input that is a single expression is echoed, so it doesn't
+// need a ';'. Anything else is parsed as a program, and a missing ';' after the last
+// statement is forgiven too.
+pub fn parse_input(source: &str) -> Result<Vec<Statement>, Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.chars().collect());
+    scanner.scan()?;
+    let tokens = scanner.tokens();
+
+    if let Ok(expr) = Parser::new(tokens.clone()).parse_expression() {
+        let span = expr.span;
+        return Ok(vec![Statement::new(StatementKind::Expression(expr), span)]);
+    }
+
+    let program = Parser::new(tokens.clone()).parse();
+    if program.is_ok() {
+        return program;
+    }
+    let mut completed = tokens;
+    let eof = completed.pop().expect("the scanner always adds Eof");
+    completed.push(Token {
+        typ: TokenType::Semicolon,
+        lexeme: ";".to_string(),
+        literal: None,
+        span: eof.span,
+    });
+    completed.push(eof);
+    Parser::new(completed).parse().or(program)
+}
+
+// Number of braces and parentheses left open, ignoring those inside strings and
+// comments.
+pub fn nesting_depth(source: &str) -> i32 {
+    let mut depth = 0;
+    let mut chars = source.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            '{' | '(' | '[' => depth += 1,
+            '}' | ')' | ']' => depth -= 1,
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => {
+                            chars.next();
+                        }
+                        _ => {}
+                    }
+                }
+            }
+            '/' if chars.peek() == Some(&'/') => {
+                for c in chars.by_ref() {
+                    if c == '\n' {
+                        break;
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    depth
+}
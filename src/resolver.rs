@@ -0,0 +1,292 @@
+use std::{cell::Cell, collections::HashMap};
+
+use crate::{
+    error::Diagnostic,
+    expression::{Expression, ExpressionKind},
+    statement::{FunctionDeclaration, Statement, StatementKind},
+    token::Token,
+};
+
+// Static pass that runs after parsing: binds every local variable usage to the number
+// of scopes between it and its declaration so the interpreter doesn't have to search
+// the environment chain by name, and reports misuse of variables before execution.
+pub struct Resolver {
+    // Each scope maps a declared name to whether its initializer has been resolved.
+    scopes: Vec<HashMap<String, bool>>,
+    current_function: FunctionType,
+    current_class: ClassType,
+    diagnostics: Vec<Diagnostic>,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum FunctionType {
+    None,
+    Function,
+    Method,
+    Initializer,
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum ClassType {
+    None,
+    Class,
+    Subclass,
+}
+
+impl Resolver {
+    pub fn new() -> Resolver {
+        Resolver {
+            scopes: vec![],
+            current_function: FunctionType::None,
+            current_class: ClassType::None,
+            diagnostics: vec![],
+        }
+    }
+
+    pub fn resolve(&mut self, statements: &[Statement]) -> Result<(), Vec<Diagnostic>> {
+        self.resolve_statements(statements);
+
+        if self.diagnostics.iter().any(Diagnostic::is_error) {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+
+        Ok(())
+    }
+
+    fn resolve_statements(&mut self, statements: &[Statement]) {
+        for stmt in statements {
+            self.resolve_statement(stmt);
+        }
+    }
+
+    fn resolve_statement(&mut self, statement: &Statement) {
+        match &statement.kind {
+            StatementKind::Expression(expr) | StatementKind::Print(expr) => {
+                self.resolve_expression(expr)
+            }
+            StatementKind::Variable(name, initializer) => {
+                self.declare(name);
+                if let Some(initializer) = initializer {
+                    self.resolve_expression(initializer);
+                }
+                self.define(name);
+            }
+            StatementKind::Block(stmts) => {
+                self.begin_scope();
+                self.resolve_statements(stmts);
+                self.end_scope();
+            }
+            StatementKind::If(cond, then_branch, else_branch) => {
+                self.resolve_expression(cond);
+                self.resolve_statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.resolve_statement(else_branch);
+                }
+            }
+            StatementKind::While(cond, body, increment) => {
+                self.resolve_expression(cond);
+                self.resolve_statement(body);
+                if let Some(increment) = increment {
+                    self.resolve_expression(increment);
+                }
+            }
+            StatementKind::Function(declaration) => {
+                self.declare(&declaration.name);
+                self.define(&declaration.name);
+                self.resolve_function(declaration, FunctionType::Function);
+            }
+            StatementKind::Class(name, superclass, methods) => {
+                let enclosing_class = self.current_class;
+                self.current_class = ClassType::Class;
+                self.declare(name);
+                self.define(name);
+
+                if let Some(superclass) = superclass {
+                    if let ExpressionKind::Variable(superclass_name, _) = &superclass.kind {
+                        if superclass_name.lexeme == name.lexeme {
+                            self.error(superclass_name, "A class can't inherit from itself");
+                        }
+                    }
+                    self.current_class = ClassType::Subclass;
+                    self.resolve_expression(superclass);
+
+                    self.begin_scope();
+                    self.define_keyword("super");
+                }
+
+                self.begin_scope();
+                self.define_keyword("this");
+                for method in methods {
+                    let typ = if method.name.lexeme == "init" {
+                        FunctionType::Initializer
+                    } else {
+                        FunctionType::Method
+                    };
+                    self.resolve_function(method, typ);
+                }
+                self.end_scope();
+
+                if superclass.is_some() {
+                    self.end_scope();
+                }
+
+                self.current_class = enclosing_class;
+            }
+            StatementKind::Return(keyword, value) => {
+                if self.current_function == FunctionType::None {
+                    self.error(keyword, "Can't return from top-level code");
+                }
+                if let Some(value) = value {
+                    if self.current_function == FunctionType::Initializer {
+                        self.error(keyword, "Can't return a value from an initializer");
+                    }
+                    self.resolve_expression(value);
+                }
+            }
+            StatementKind::Break(_) | StatementKind::Continue(_) => {}
+        }
+    }
+
+    fn resolve_function(&mut self, declaration: &FunctionDeclaration, typ: FunctionType) {
+        let enclosing_function = self.current_function;
+        self.current_function = typ;
+
+        self.begin_scope();
+        for param in &declaration.params {
+            self.declare(param);
+            self.define(param);
+        }
+        self.resolve_statements(&declaration.body);
+        self.end_scope();
+
+        self.current_function = enclosing_function;
+    }
+
+    fn resolve_expression(&mut self, expression: &Expression) {
+        match &expression.kind {
+            ExpressionKind::Binary(lhs, _, rhs) | ExpressionKind::Logical(lhs, _, rhs) => {
+                self.resolve_expression(lhs);
+                self.resolve_expression(rhs);
+            }
+            ExpressionKind::Unary(_, rhs) => self.resolve_expression(rhs),
+            ExpressionKind::Literal(_) => {}
+            ExpressionKind::Grouping(group) | ExpressionKind::Stringify(group) => {
+                self.resolve_expression(group)
+            }
+            ExpressionKind::Variable(name, depth) => {
+                let is_uninitialized = self
+                    .scopes
+                    .last()
+                    .is_some_and(|scope| scope.get(&name.lexeme) == Some(&false));
+                if is_uninitialized {
+                    let diagnostic =
+                        Diagnostic::error(name, "Can't read local variable in its own initializer")
+                            .with_help("give the local variable a different name");
+                    self.diagnostics.push(diagnostic);
+                }
+                self.resolve_local(name, depth);
+            }
+            ExpressionKind::Assign(name, value, depth) => {
+                self.resolve_expression(value);
+                self.resolve_local(name, depth);
+            }
+            ExpressionKind::Call(callee, _, args) => {
+                self.resolve_expression(callee);
+                for arg in args {
+                    self.resolve_expression(arg);
+                }
+            }
+            ExpressionKind::Get(object, _) => self.resolve_expression(object),
+            ExpressionKind::Set(object, _, value) => {
+                self.resolve_expression(value);
+                self.resolve_expression(object);
+            }
+            ExpressionKind::List(elements) => {
+                for element in elements {
+                    self.resolve_expression(element);
+                }
+            }
+            ExpressionKind::Map(entries) => {
+                for (key, value) in entries {
+                    self.resolve_expression(key);
+                    self.resolve_expression(value);
+                }
+            }
+            ExpressionKind::Index(object, _, index) => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+            }
+            ExpressionKind::SetIndex(object, _, index, value) => {
+                self.resolve_expression(object);
+                self.resolve_expression(index);
+                self.resolve_expression(value);
+            }
+            ExpressionKind::This(keyword, depth) => {
+                if self.current_class == ClassType::None {
+                    self.error(keyword, "Can't use 'this' outside of a class");
+                    return;
+                }
+                self.resolve_local(keyword, depth);
+            }
+            ExpressionKind::Super(keyword, _, depth) => {
+                match self.current_class {
+                    ClassType::None => self.error(keyword, "Can't use 'super' outside of a class"),
+                    ClassType::Class => {
+                        self.error(keyword, "Can't use 'super' in a class with no superclass")
+                    }
+                    ClassType::Subclass => {}
+                }
+                self.resolve_local(keyword, depth);
+            }
+        }
+    }
+
+    fn resolve_local(&mut self, name: &Token, depth: &Cell<Option<usize>>) {
+        let found = self
+            .scopes
+            .iter()
+            .rev()
+            .position(|scope| scope.contains_key(&name.lexeme));
+        depth.set(found);
+    }
+
+    fn error(&mut self, token: &Token, message: &str) {
+        self.diagnostics.push(Diagnostic::error(token, message));
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        self.scopes.pop();
+    }
+
+    fn declare(&mut self, name: &Token) {
+        let Some(scope) = self.scopes.last_mut() else {
+            return;
+        };
+
+        if scope.insert(name.lexeme.clone(), false).is_some() {
+            self.error(name, "Already a variable with this name in this scope");
+        }
+    }
+
+    fn define_keyword(&mut self, keyword: &str) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(keyword.to_string(), true);
+        }
+    }
+
+    fn define(&mut self, name: &Token) {
+        if let Some(scope) = self.scopes.last_mut() {
+            scope.insert(name.lexeme.clone(), true);
+        }
+    }
+}
+
+impl Default for Resolver {
+    fn default() -> Self {
+        Resolver::new()
+    }
+}
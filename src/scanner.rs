@@ -1,23 +1,39 @@
 use std::collections::HashMap;
 
+use crate::error::Diagnostic;
 use crate::token::Literal;
+use crate::token::Span;
 use crate::token::Token;
 use crate::token::TokenType;
+use crate::value::Number;
 
 pub struct Scanner {
     source: Vec<char>,
+    // Byte offset of every symbol in the original text, plus one for the end of input.
+    byte_offsets: Vec<usize>,
     tokens: Vec<Token>,
     start: usize,
     current: usize,
     line: u32,
+    // Index of the first symbol of the current line, used to compute token columns.
+    line_start: usize,
+    // Position where the token being scanned begins, multi-line strings report this.
+    start_line: u32,
+    start_column: u32,
     keywords: HashMap<String, TokenType>,
+    diagnostics: Vec<Diagnostic>,
+    // One entry per `${` being scanned, counting the braces opened inside it so that
+    // only the matching `}` resumes the string.
+    interpolations: Vec<usize>,
 }
 
 impl Scanner {
     pub fn new(source: Vec<char>) -> Scanner {
         let keywords: HashMap<String, TokenType> = [
             ("and".to_string(), TokenType::And),
+            ("break".to_string(), TokenType::Break),
             ("class".to_string(), TokenType::Class),
+            ("continue".to_string(), TokenType::Continue),
             ("else".to_string(), TokenType::Else),
             ("false".to_string(), TokenType::False),
             ("for".to_string(), TokenType::For),
@@ -26,7 +42,7 @@ impl Scanner {
             ("nil".to_string(), TokenType::Nil),
             ("or".to_string(), TokenType::Or),
             ("print".to_string(), TokenType::Print),
-            ("return".to_string(), TokenType::Print),
+            ("return".to_string(), TokenType::Return),
             ("super".to_string(), TokenType::Super),
             ("this".to_string(), TokenType::This),
             ("true".to_string(), TokenType::True),
@@ -34,28 +50,51 @@ impl Scanner {
             ("while".to_string(), TokenType::While),
         ]
         .into();
+        let mut byte_offsets = Vec::with_capacity(source.len() + 1);
+        let mut offset = 0;
+        for c in &source {
+            byte_offsets.push(offset);
+            offset += c.len_utf8();
+        }
+        byte_offsets.push(offset);
+
         Scanner {
             source,
+            byte_offsets,
             tokens: vec![],
             start: 0,
             current: 0,
             line: 1,
+            line_start: 0,
+            start_line: 1,
+            start_column: 1,
             keywords,
+            diagnostics: vec![],
+            interpolations: vec![],
         }
     }
 
-    pub fn scan(&mut self) {
+    pub fn scan(&mut self) -> Result<(), Vec<Diagnostic>> {
         while !self.is_end() {
             self.start = self.current;
+            self.start_line = self.line;
+            self.start_column = (self.start - self.line_start) as u32 + 1;
             self.scan_token();
         }
 
-        self.tokens.push(Token {
-            typ: TokenType::Eof,
-            lexeme: "".to_string(),
-            literal: None,
-            line: self.line,
-        });
+        self.start = self.current;
+        self.start_line = self.line;
+        self.start_column = (self.start - self.line_start) as u32 + 1;
+        if !self.interpolations.is_empty() {
+            self.error("Unterminated string interpolation");
+        }
+        self.add_token_without_lexeme(TokenType::Eof);
+
+        if !self.diagnostics.is_empty() {
+            return Err(std::mem::take(&mut self.diagnostics));
+        }
+
+        Ok(())
     }
 
     pub fn tokens(&self) -> Vec<Token> {
@@ -70,7 +109,7 @@ impl Scanner {
     fn scan_token(&mut self) {
         let symbol = self.advance();
         if !self.match_symbol(symbol) {
-            eprintln!("Unexpected symbol: {}", symbol)
+            self.error("Unexpected symbol");
         }
     }
 
@@ -86,15 +125,16 @@ impl Scanner {
     fn match_operator(&mut self, symbol: char) -> bool {
         match symbol {
             ',' => self.add_token_without_lexeme(TokenType::Comma),
+            ':' => self.add_token_without_lexeme(TokenType::Colon),
             '.' => self.add_token_without_lexeme(TokenType::Dot),
             '-' => self.add_token_without_lexeme(TokenType::Minus),
             '+' => self.add_token_without_lexeme(TokenType::Plus),
             ';' => self.add_token_without_lexeme(TokenType::Semicolon),
             '*' => self.add_token_without_lexeme(TokenType::Star),
-            '!' => self.add_long_operator('=', TokenType::NotEqual, TokenType::Equal),
+            '!' => self.add_long_operator('=', TokenType::NotEqual, TokenType::Not),
             '=' => self.add_long_operator('=', TokenType::EqualEqual, TokenType::Equal),
             '<' => self.add_long_operator('=', TokenType::LessEqual, TokenType::Less),
-            '>' => self.add_long_operator('>', TokenType::GreaterEqual, TokenType::Greater),
+            '>' => self.add_long_operator('=', TokenType::GreaterEqual, TokenType::Greater),
             '/' => {
                 if self.match_and_advance('/') {
                     while self.peek().is_some_and(|c| c != '\n') && !self.is_end() {
@@ -114,8 +154,26 @@ impl Scanner {
         match symbol {
             '(' => self.add_token_without_lexeme(TokenType::LeftParenthesis),
             ')' => self.add_token_without_lexeme(TokenType::RightParenthesis),
-            '{' => self.add_token_without_lexeme(TokenType::LeftBrace),
-            '}' => self.add_token_without_lexeme(TokenType::RightBrace),
+            '{' => {
+                if let Some(depth) = self.interpolations.last_mut() {
+                    *depth += 1;
+                }
+                self.add_token_without_lexeme(TokenType::LeftBrace);
+            }
+            '}' => match self.interpolations.last_mut() {
+                Some(0) => {
+                    self.interpolations.pop();
+                    self.string_contents();
+                }
+                depth => {
+                    if let Some(depth) = depth {
+                        *depth -= 1;
+                    }
+                    self.add_token_without_lexeme(TokenType::RightBrace);
+                }
+            },
+            '[' => self.add_token_without_lexeme(TokenType::LeftBracket),
+            ']' => self.add_token_without_lexeme(TokenType::RightBracket),
             _ => return false,
         }
 
@@ -125,7 +183,7 @@ impl Scanner {
     fn match_escape(&mut self, symbol: char) -> bool {
         match symbol {
             ' ' | '\r' | '\t' => {}
-            '\n' => self.line += 1,
+            '\n' => self.new_line(),
             _ => return false,
         }
 
@@ -137,26 +195,105 @@ impl Scanner {
             return false;
         }
 
-        while self.peek().is_some_and(|c| c != '"') && !self.is_end() {
-            if self.peek().is_some_and(|c| c == '\n') {
-                self.line += 1;
+        self.string_contents();
+        true
+    }
+
+    // Scans up to the closing quote, or up to a `${` which ends the token as an
+    // `Interpolation`. The string continues after the `}` closing the expression.
+    fn string_contents(&mut self) {
+        let mut string = String::new();
+        loop {
+            match self.peek() {
+                None => {
+                    self.error("Unterminated string");
+                    return;
+                }
+                Some('"') => {
+                    self.advance();
+                    self.add_token(TokenType::String, Some(Literal::String(string)));
+                    return;
+                }
+                Some('$') if self.peek_next() == Some('{') => {
+                    self.current += 2;
+                    self.interpolations.push(0);
+                    self.add_token(TokenType::Interpolation, Some(Literal::String(string)));
+                    return;
+                }
+                Some('\\') => {
+                    self.advance();
+                    if let Some(c) = self.escape() {
+                        string.push(c);
+                    }
+                }
+                Some(c) => {
+                    self.advance();
+                    if c == '\n' {
+                        self.new_line();
+                    }
+                    string.push(c);
+                }
             }
-            self.advance();
         }
+    }
 
-        if self.is_end() {
-            // TODO: replace with custom error log
-            eprintln!("Unterminated string found.");
-            return false;
+    // Character written by the escape sequence after a backslash, `None` once an
+    // invalid sequence has been reported.
+    fn escape(&mut self) -> Option<char> {
+        let start = self.current - 1;
+        let Some(symbol) = self.peek().filter(|&c| c != '\n') else {
+            self.error_from(start, "Unfinished escape sequence");
+            return None;
+        };
+        self.advance();
+
+        let c = match symbol {
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            '0' => '\0',
+            '\\' => '\\',
+            '"' => '"',
+            '$' => '$',
+            'u' => return self.unicode_escape(start),
+            _ => {
+                self.error_from(start, &format!("Invalid escape sequence '\\{}'", symbol));
+                return None;
+            }
+        };
+        Some(c)
+    }
+
+    // `\u{...}` with one to six hex digits naming a Unicode scalar value.
+    fn unicode_escape(&mut self, start: usize) -> Option<char> {
+        if !self.match_and_advance('{') {
+            self.error_from(start, "Expected '{' after '\\u'");
+            return None;
         }
 
-        self.advance();
-        let string: String = self.source[self.start + 1..self.current - 1]
-            .iter()
-            .collect();
-        self.add_token(TokenType::String, Some(Literal::String(string)));
+        let digits_start = self.current;
+        while self.peek().is_some_and(|c| c.is_ascii_hexdigit()) {
+            self.advance();
+        }
+        let digits: String = self.source[digits_start..self.current].iter().collect();
+        if !self.match_and_advance('}') || digits.is_empty() || digits.len() > 6 {
+            self.error_from(
+                start,
+                "Invalid unicode escape, expected 1 to 6 hex digits in braces",
+            );
+            return None;
+        }
 
-        true
+        let c = u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32);
+        if c.is_none() {
+            self.error_from(
+                start,
+                &format!("'{}' is not a Unicode scalar value", digits),
+            );
+        }
+        c
     }
 
     fn match_number(&mut self, symbol: char) -> bool {
@@ -164,23 +301,100 @@ impl Scanner {
             return false;
         }
 
-        while self.peek().is_some_and(Scanner::is_digit) {
-            self.advance();
+        let number = match (symbol, self.peek()) {
+            ('0', Some('x' | 'X')) => self.radix_number(16, "hex"),
+            ('0', Some('b' | 'B')) => self.radix_number(2, "binary"),
+            _ => self.decimal_number(),
+        };
+        match number {
+            Ok(number) => self.add_token(TokenType::Number, Some(Literal::Number(number))),
+            Err(message) => self.error(&message),
         }
 
+        true
+    }
+
+    // `12`, `1_000`, `3.25` or `6.674e-11`, the first digit is already consumed.
+    fn decimal_number(&mut self) -> Result<Number, String> {
+        self.skip_digits();
         let is_next_digit = self.peek_next().is_some_and(Scanner::is_digit);
-        if self.peek().is_some_and(|c| c == '.') && is_next_digit {
+        if self.peek() == Some('.') && is_next_digit {
+            self.advance();
+            self.skip_digits();
+        }
+        if matches!(self.peek(), Some('e' | 'E')) {
             self.advance();
-            while self.peek().is_some_and(Scanner::is_digit) {
+            if matches!(self.peek(), Some('+' | '-')) {
                 self.advance();
             }
+            if !self.peek().is_some_and(Scanner::is_digit) {
+                self.skip_alphanumeric();
+                return Err("Expected digits in the exponent".to_string());
+            }
+            self.skip_digits();
+        }
+        self.check_number_end()?;
+
+        let text: String = self.source[self.start..self.current].iter().collect();
+        check_separators(&text, 10)?;
+        let number: Number = text
+            .replace('_', "")
+            .parse()
+            .map_err(|_| "Invalid number literal".to_string())?;
+        if number.is_infinite() {
+            return Err("Number literal is too large".to_string());
         }
+        Ok(number)
+    }
 
-        let number: String = self.source[self.start..self.current].iter().collect();
-        let number: f32 = number.parse().unwrap();
-        self.add_token(TokenType::Number, Some(Literal::Number(number)));
+    // `0xFF` or `0b1010`, the `0` is already consumed.
+    fn radix_number(&mut self, radix: u32, name: &str) -> Result<Number, String> {
+        let prefix = self.advance();
+        self.skip_alphanumeric();
+        let text: String = self.source[self.start + 2..self.current].iter().collect();
 
-        true
+        if let Some(c) = text.chars().find(|&c| c != '_' && !c.is_digit(radix)) {
+            return Err(format!("Invalid digit '{}' in {} literal", c, name));
+        }
+        let digits = text.replace('_', "");
+        if digits.is_empty() {
+            return Err(format!("Expected {} digits after '0{}'", name, prefix));
+        }
+        check_separators(&text, radix)?;
+        // Like decimal literals, values past 2^53 round to the nearest number.
+        u64::from_str_radix(&digits, radix)
+            .map(|n| n as Number)
+            .map_err(|_| "Number literal is too large".to_string())
+    }
+
+    fn skip_digits(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| Scanner::is_digit(c) || c == '_')
+        {
+            self.advance();
+        }
+    }
+
+    fn skip_alphanumeric(&mut self) {
+        while self
+            .peek()
+            .is_some_and(|c| c.is_ascii_alphanumeric() || c == '_')
+        {
+            self.advance();
+        }
+    }
+
+    // A letter right after a number is a typo in the literal rather than the start of
+    // an identifier.
+    fn check_number_end(&mut self) -> Result<(), String> {
+        match self.peek() {
+            Some(c) if c.is_ascii_alphabetic() => {
+                self.skip_alphanumeric();
+                Err(format!("Invalid character '{}' in number literal", c))
+            }
+            _ => Ok(()),
+        }
     }
 
     fn match_identifier(&mut self, symbol: char) -> bool {
@@ -246,10 +460,48 @@ impl Scanner {
             typ,
             lexeme,
             literal,
-            line: self.line,
+            span: self.span(),
         });
     }
 
+    fn error(&mut self, message: &str) {
+        let lexeme: String = self.source[self.start..self.current].iter().collect();
+        self.diagnostics
+            .push(Diagnostic::at(self.span(), &lexeme, message));
+    }
+
+    // Error for the part of the current token starting at `start`, which has to be on
+    // the current line.
+    fn error_from(&mut self, start: usize, message: &str) {
+        let lexeme: String = self.source[start..self.current].iter().collect();
+        let span = Span {
+            start: self.byte_offsets[start],
+            end: self.byte_offsets[self.current],
+            line: self.line,
+            column: (start - self.line_start) as u32 + 1,
+            end_line: self.line,
+            end_column: (self.current - self.line_start) as u32 + 1,
+        };
+        self.diagnostics
+            .push(Diagnostic::at(span, &lexeme, message));
+    }
+
+    fn span(&self) -> Span {
+        Span {
+            start: self.byte_offsets[self.start],
+            end: self.byte_offsets[self.current],
+            line: self.start_line,
+            column: self.start_column,
+            end_line: self.line,
+            end_column: (self.current - self.line_start) as u32 + 1,
+        }
+    }
+
+    fn new_line(&mut self) {
+        self.line += 1;
+        self.line_start = self.current;
+    }
+
     fn match_and_advance(&mut self, expected: char) -> bool {
         if self.is_end() {
             return false;
@@ -302,3 +554,18 @@ impl Scanner {
         });
     }
 }
+
+// Digit separators may only appear between two digits.
+fn check_separators(text: &str, radix: u32) -> Result<(), String> {
+    let chars: Vec<char> = text.chars().collect();
+    let is_digit = |i: Option<usize>| {
+        i.and_then(|i| chars.get(i))
+            .is_some_and(|c| c.is_digit(radix))
+    };
+    for (i, _) in chars.iter().enumerate().filter(|(_, &c)| c == '_') {
+        if !is_digit(i.checked_sub(1)) || !is_digit(Some(i + 1)) {
+            return Err("'_' can only separate digits".to_string());
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,457 @@
+use std::rc::Rc;
+
+use crate::{
+    chunk::{Chunk, Constant, FunctionProto, OpCode},
+    error::BytecodeError,
+    token::Span,
+    value::Number,
+};
+
+// Layout of a compiled file, all integers little endian:
+//
+//   magic "LOXC" | format version: u16 | script function | CRC-32 of everything before
+//
+// A function is its name, arity, upvalue count, code, constant pool and line table.
+// Nested functions are stored inline in the constant pool of their parent.
+pub const MAGIC: &[u8; 4] = b"LOXC";
+// Bump whenever the instruction set or the encoding below changes.
+pub const FORMAT_VERSION: u16 = 5;
+
+const CONSTANT_NUMBER: u8 = 0;
+const CONSTANT_STRING: u8 = 1;
+const CONSTANT_FUNCTION: u8 = 2;
+
+// Guards the recursive reader against hostile files.
+const MAX_NESTING: usize = 256;
+
+pub fn is_bytecode(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+pub fn serialize(function: &FunctionProto) -> Vec<u8> {
+    let mut writer = Writer { bytes: vec![] };
+    writer.bytes.extend_from_slice(MAGIC);
+    writer.u16(FORMAT_VERSION);
+    writer.function(function);
+
+    let checksum = crc32(&writer.bytes);
+    writer.u32(checksum);
+    writer.bytes
+}
+
+pub fn deserialize(bytes: &[u8]) -> Result<Rc<FunctionProto>, BytecodeError> {
+    if !is_bytecode(bytes) {
+        return Err(BytecodeError::NotBytecode);
+    }
+
+    // Check the version first so old files get a helpful message even if the rest of
+    // the layout has changed since.
+    let mut reader = Reader {
+        bytes,
+        position: MAGIC.len(),
+    };
+    let version = reader.u16()?;
+    if version != FORMAT_VERSION {
+        return Err(BytecodeError::IncompatibleVersion {
+            found: version,
+            expected: FORMAT_VERSION,
+        });
+    }
+
+    let Some(body_len) = bytes.len().checked_sub(4) else {
+        return Err(BytecodeError::Truncated);
+    };
+    let (body, checksum) = bytes.split_at(body_len);
+    if checksum.len() < 4 || crc32(body) != u32::from_le_bytes(checksum.try_into().unwrap()) {
+        return Err(BytecodeError::ChecksumMismatch);
+    }
+
+    reader.bytes = body;
+    let function = reader.function(0)?;
+    if reader.position != body.len() {
+        return Err(malformed("trailing bytes after the script"));
+    }
+    // The script has no enclosing function to capture variables from, and nothing
+    // passes it arguments.
+    if function.upvalue_count > 0 {
+        return Err(malformed("the script can't capture variables"));
+    }
+    if function.arity > 0 {
+        return Err(malformed("the script can't take parameters"));
+    }
+    verify(&function)?;
+
+    Ok(Rc::new(function))
+}
+
+struct Writer {
+    bytes: Vec<u8>,
+}
+
+impl Writer {
+    fn function(&mut self, function: &FunctionProto) {
+        self.string(&function.name);
+        self.u32(function.arity as u32);
+        self.u32(function.upvalue_count as u32);
+        self.chunk(&function.chunk);
+    }
+
+    fn chunk(&mut self, chunk: &Chunk) {
+        self.u32(chunk.code.len() as u32);
+        self.bytes.extend_from_slice(&chunk.code);
+
+        self.u32(chunk.constants.len() as u32);
+        for constant in &chunk.constants {
+            match constant {
+                Constant::Number(n) => {
+                    self.bytes.push(CONSTANT_NUMBER);
+                    self.bytes.extend_from_slice(&n.to_le_bytes());
+                }
+                Constant::String(s) => {
+                    self.bytes.push(CONSTANT_STRING);
+                    self.string(s);
+                }
+                Constant::Function(function) => {
+                    self.bytes.push(CONSTANT_FUNCTION);
+                    self.function(function);
+                }
+            }
+        }
+
+        self.u32(chunk.spans.len() as u32);
+        for (offset, span) in &chunk.spans {
+            self.u32(*offset as u32);
+            self.u32(span.start as u32);
+            self.u32(span.end as u32);
+            self.u32(span.line);
+            self.u32(span.column);
+            self.u32(span.end_line);
+            self.u32(span.end_column);
+        }
+    }
+
+    fn string(&mut self, s: &str) {
+        self.u32(s.len() as u32);
+        self.bytes.extend_from_slice(s.as_bytes());
+    }
+
+    fn u16(&mut self, value: u16) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn u32(&mut self, value: u32) {
+        self.bytes.extend_from_slice(&value.to_le_bytes());
+    }
+}
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+    position: usize,
+}
+
+impl Reader<'_> {
+    fn function(&mut self, depth: usize) -> Result<FunctionProto, BytecodeError> {
+        if depth > MAX_NESTING {
+            return Err(malformed("functions nested too deeply"));
+        }
+
+        Ok(FunctionProto {
+            name: self.string()?,
+            arity: self.u32()? as usize,
+            upvalue_count: self.u32()? as usize,
+            chunk: self.chunk(depth)?,
+        })
+    }
+
+    fn chunk(&mut self, depth: usize) -> Result<Chunk, BytecodeError> {
+        let code_len = self.u32()? as usize;
+        let code = self.take(code_len)?.to_vec();
+
+        let constant_count = self.u32()?;
+        let mut constants = vec![];
+        for _ in 0..constant_count {
+            let constant = match self.take(1)?[0] {
+                CONSTANT_NUMBER => {
+                    let bytes = self.take(std::mem::size_of::<Number>())?;
+                    Constant::Number(Number::from_le_bytes(bytes.try_into().unwrap()))
+                }
+                CONSTANT_STRING => Constant::String(Rc::from(self.string()?)),
+                CONSTANT_FUNCTION => Constant::Function(Rc::new(self.function(depth + 1)?)),
+                tag => return Err(malformed(&format!("unknown constant tag {}", tag))),
+            };
+            constants.push(constant);
+        }
+
+        let span_count = self.u32()?;
+        let mut spans = vec![];
+        for _ in 0..span_count {
+            let offset = self.u32()? as usize;
+            let span = Span {
+                start: self.u32()? as usize,
+                end: self.u32()? as usize,
+                line: self.u32()?,
+                column: self.u32()?,
+                end_line: self.u32()?,
+                end_column: self.u32()?,
+            };
+            spans.push((offset, span));
+        }
+
+        Ok(Chunk {
+            code,
+            constants,
+            spans,
+        })
+    }
+
+    fn string(&mut self) -> Result<String, BytecodeError> {
+        let len = self.u32()? as usize;
+        let bytes = self.take(len)?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| malformed("string is not valid UTF-8"))
+    }
+
+    fn u16(&mut self) -> Result<u16, BytecodeError> {
+        Ok(u16::from_le_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, BytecodeError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn take(&mut self, len: usize) -> Result<&[u8], BytecodeError> {
+        let end = self
+            .position
+            .checked_add(len)
+            .filter(|end| *end <= self.bytes.len())
+            .ok_or(BytecodeError::Truncated)?;
+        let bytes = &self.bytes[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+}
+
+// Checks the structure of the code: every instruction is known and complete,
+// operands refer to constants of the right kind and upvalues that exist, and the
+// stack is used consistently (see `verify_stack`). The kinds of values on the stack
+// aren't tracked, the VM checks those where it depends on them.
+fn verify(function: &FunctionProto) -> Result<(), BytecodeError> {
+    let chunk = &function.chunk;
+    let code = &chunk.code;
+    let name = |index: u16| match chunk.constants.get(index as usize) {
+        Some(Constant::String(_)) => Ok(()),
+        _ => Err(malformed(&format!("{} expects a name constant", function))),
+    };
+
+    // Offset of the following instruction for every offset an instruction starts at.
+    let mut ends = vec![None; code.len()];
+    let mut offset = 0;
+    while offset < code.len() {
+        let Some(op) = OpCode::from_byte(code[offset]) else {
+            return Err(malformed(&format!("unknown opcode {}", code[offset])));
+        };
+        let operands = offset + 1;
+        let mut next = operands + op.operand_len();
+        if next > code.len() {
+            return Err(malformed(&format!("{:?} is missing its operands", op)));
+        }
+        let u16_at = |at: usize| u16::from_be_bytes([code[at], code[at + 1]]);
+
+        match op {
+            OpCode::Constant => match chunk.constants.get(u16_at(operands) as usize) {
+                Some(Constant::Number(_) | Constant::String(_)) => {}
+                _ => return Err(malformed("Constant expects a number or string")),
+            },
+            OpCode::GetGlobal
+            | OpCode::DefineGlobal
+            | OpCode::SetGlobal
+            | OpCode::GetProperty
+            | OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Class
+            | OpCode::Method => name(u16_at(operands))?,
+            OpCode::GetLocalChecked => name(u16_at(operands + 1))?,
+            OpCode::GetUpvalue | OpCode::SetUpvalue | OpCode::GetUpvalueChecked => {
+                if code[operands] as usize >= function.upvalue_count {
+                    return Err(malformed("upvalue index out of range"));
+                }
+                if op == OpCode::GetUpvalueChecked {
+                    name(u16_at(operands + 1))?;
+                }
+            }
+            OpCode::Jump | OpCode::JumpIfFalse | OpCode::JumpIfNotBool
+                if next + u16_at(operands) as usize > code.len() =>
+            {
+                return Err(malformed("jump past the end of the chunk"));
+            }
+            OpCode::Loop if u16_at(operands) as usize > next => {
+                return Err(malformed("loop before the start of the chunk"));
+            }
+            OpCode::Closure => {
+                let Some(Constant::Function(nested)) =
+                    chunk.constants.get(u16_at(operands) as usize)
+                else {
+                    return Err(malformed("Closure expects a function constant"));
+                };
+                for _ in 0..nested.upvalue_count {
+                    if next + 2 > code.len() {
+                        return Err(malformed("Closure is missing its upvalues"));
+                    }
+                    let (is_local, index) = (code[next], code[next + 1] as usize);
+                    if is_local > 1 || (is_local == 0 && index >= function.upvalue_count) {
+                        return Err(malformed("invalid upvalue capture"));
+                    }
+                    next += 2;
+                }
+                verify(nested)?;
+            }
+            _ => {}
+        }
+
+        ends[offset] = Some(next);
+        offset = next;
+    }
+
+    verify_stack(function, &ends)
+}
+
+// Follows every path through the code with the height of the stack above the frame's
+// base, which starts out holding the callee and its arguments. Instructions must not
+// pop below the base or use local slots above the top, jumps must land on the start
+// of an instruction, paths that meet must agree on the height and every path must
+// end in a return.
+fn verify_stack(function: &FunctionProto, ends: &[Option<usize>]) -> Result<(), BytecodeError> {
+    let code = &function.chunk.code;
+    let mut heights = vec![None; code.len()];
+    let mut pending = vec![(0, function.arity + 1)];
+    while let Some((offset, height)) = pending.pop() {
+        if offset >= code.len() {
+            return Err(malformed(&format!(
+                "{} doesn't end with a return",
+                function
+            )));
+        }
+        let Some(next) = ends[offset] else {
+            return Err(malformed("jump into the middle of an instruction"));
+        };
+        match heights[offset] {
+            Some(known) if known == height => continue,
+            Some(_) => {
+                return Err(malformed(
+                    "paths reach an instruction with different stack heights",
+                ))
+            }
+            None => heights[offset] = Some(height),
+        }
+
+        let op = OpCode::from_byte(code[offset]).expect("checked by verify");
+        let operands = offset + 1;
+        let u16_at = |at: usize| u16::from_be_bytes([code[at], code[at + 1]]) as usize;
+        let local = |slot: u8| {
+            if slot as usize >= height {
+                return Err(malformed(&format!("local slot {} out of range", slot)));
+            }
+            Ok(())
+        };
+
+        // Values the instruction needs on the stack and the number it leaves in
+        // their place.
+        let (needs, leaves) = match op {
+            OpCode::Constant
+            | OpCode::Nil
+            | OpCode::True
+            | OpCode::False
+            | OpCode::Uninitialized
+            | OpCode::GetGlobal
+            | OpCode::GetUpvalue
+            | OpCode::GetUpvalueChecked
+            | OpCode::Class => (0, 1),
+            OpCode::GetLocal | OpCode::GetLocalChecked => {
+                local(code[operands])?;
+                (0, 1)
+            }
+            OpCode::SetLocal => {
+                local(code[operands])?;
+                (1, 1)
+            }
+            OpCode::Closure => {
+                for capture in code[operands + 2..next].chunks_exact(2) {
+                    if capture[0] == 1 {
+                        local(capture[1])?;
+                    }
+                }
+                (0, 1)
+            }
+            OpCode::Pop
+            | OpCode::DefineGlobal
+            | OpCode::Print
+            | OpCode::CloseUpvalue
+            | OpCode::Return => (1, 0),
+            OpCode::SetGlobal
+            | OpCode::SetUpvalue
+            | OpCode::GetProperty
+            | OpCode::Not
+            | OpCode::Negate
+            | OpCode::Stringify
+            | OpCode::JumpIfFalse
+            | OpCode::JumpIfNotBool => (1, 1),
+            OpCode::SetProperty
+            | OpCode::GetSuper
+            | OpCode::Equal
+            | OpCode::NotEqual
+            | OpCode::Greater
+            | OpCode::GreaterEqual
+            | OpCode::Less
+            | OpCode::LessEqual
+            | OpCode::Add
+            | OpCode::Subtract
+            | OpCode::Multiply
+            | OpCode::Divide
+            | OpCode::Inherit
+            | OpCode::Method
+            | OpCode::GetIndex => (2, 1),
+            OpCode::SetIndex => (3, 1),
+            OpCode::Call => (code[operands] as usize + 1, 1),
+            OpCode::BuildList => (u16_at(operands), 1),
+            OpCode::BuildMap => (2 * u16_at(operands), 1),
+            OpCode::Jump | OpCode::Loop => (0, 0),
+        };
+        if needs > height {
+            return Err(malformed(&format!("{:?} pops below the frame", op)));
+        }
+        let after = height - needs + leaves;
+
+        match op {
+            OpCode::Return => {}
+            OpCode::Jump => pending.push((next + u16_at(operands), after)),
+            OpCode::Loop => pending.push((next - u16_at(operands), after)),
+            OpCode::JumpIfFalse => {
+                pending.push((next + u16_at(operands), after));
+                pending.push((next, after));
+            }
+            // The legacy dialect pops the condition when it jumps.
+            OpCode::JumpIfNotBool => {
+                pending.push((next + u16_at(operands), after - 1));
+                pending.push((next, after));
+            }
+            _ => pending.push((next, after)),
+        }
+    }
+    Ok(())
+}
+
+fn malformed(reason: &str) -> BytecodeError {
+    BytecodeError::Malformed(reason.to_string())
+}
+
+// CRC-32 (IEEE), as used by zip and PNG.
+fn crc32(bytes: &[u8]) -> u32 {
+    let mut crc = !0u32;
+    for byte in bytes {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+    !crc
+}
@@ -1,10 +1,41 @@
-use crate::{expression::Expression, token::Token};
+use std::rc::Rc;
 
-pub enum Statement {
+use crate::{
+    expression::Expression,
+    token::{Span, Token},
+};
+
+pub struct FunctionDeclaration {
+    pub name: Token,
+    pub params: Vec<Token>,
+    pub body: Vec<Statement>,
+    pub span: Span,
+}
+
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+impl Statement {
+    pub fn new(kind: StatementKind, span: Span) -> Statement {
+        Statement { kind, span }
+    }
+}
+
+pub enum StatementKind {
     Expression(Expression),
     Print(Expression),
     Variable(Token, Option<Expression>),
     Block(Vec<Statement>),
     If(Expression, Box<Statement>, Option<Box<Statement>>),
-    While(Expression, Box<Statement>),
+    // Condition, body and the increment of a desugared `for`, which also runs after a
+    // `continue`.
+    While(Expression, Box<Statement>, Option<Expression>),
+    Function(Rc<FunctionDeclaration>),
+    // Name, superclass variable and methods.
+    Class(Token, Option<Expression>, Vec<Rc<FunctionDeclaration>>),
+    Return(Token, Option<Expression>),
+    Break(Token),
+    Continue(Token),
 }
@@ -1,10 +1,15 @@
+use crate::value::Number;
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum TokenType {
     LeftParenthesis,
     RightParenthesis,
     LeftBrace,
     RightBrace,
+    LeftBracket,
+    RightBracket,
     Comma,
+    Colon,
     Dot,
     Minus,
     Plus,
@@ -21,9 +26,13 @@ pub enum TokenType {
     LessEqual,
     Identifier,
     String,
+    // Part of a string literal that is followed by an interpolated expression.
+    Interpolation,
     Number,
     And,
+    Break,
     Class,
+    Continue,
     Else,
     False,
     Fun,
@@ -43,16 +52,60 @@ pub enum TokenType {
 
 #[derive(Clone, Debug)]
 pub enum Literal {
-    Number(f32),
+    Number(Number),
     String(String),
     Bool(bool),
     Nil,
 }
 
+// Region of the source covered by a token or syntax tree node. Offsets are in bytes and
+// the end is exclusive, lines and columns are 1-based with columns counted in characters.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: u32,
+    pub column: u32,
+    pub end_line: u32,
+    pub end_column: u32,
+}
+
+impl Span {
+    // Smallest span covering both `self` and `other`, which must come after it.
+    pub fn to(self, other: Span) -> Span {
+        Span {
+            end: other.end,
+            end_line: other.end_line,
+            end_column: other.end_column,
+            ..self
+        }
+    }
+
+    // The final character of the span, such as the closing brace of a block.
+    pub fn last(self) -> Span {
+        Span {
+            start: self.end.saturating_sub(1),
+            line: self.end_line,
+            column: self.end_column.saturating_sub(1).max(1),
+            ..self
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct Token {
     pub typ: TokenType,
     pub lexeme: String,
     pub literal: Option<Literal>,
-    pub line: u32,
+    pub span: Span,
+}
+
+impl Token {
+    pub fn line(&self) -> u32 {
+        self.span.line
+    }
+
+    pub fn column(&self) -> u32 {
+        self.span.column
+    }
 }
@@ -1,9 +1,170 @@
-pub type Number = f32;
+use std::{cell::RefCell, rc::Rc};
+
+use crate::{
+    class::{Class, Instance},
+    error::RuntimeErrorKind,
+    function::{Function, NativeFunction},
+    map::{self, Key},
+};
+
+pub type Number = f64;
+
+// Deepest call nesting either backend allows before reporting a stack overflow,
+// counting the frame of the script itself.
+pub const MAX_FRAMES: usize = 1024;
+
+// Rules for truthiness and equality. `Lox` follows the language: only `nil` and
+// `false` are falsey and any two values can be compared with `==`. `Legacy` keeps
+// the original jlox rules for scripts that depend on them: `0` and `""` are falsey
+// too, `if` runs neither branch for a condition that isn't a boolean, `!` only
+// negates booleans and `==`/`!=` only compare numbers.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Dialect {
+    #[default]
+    Lox,
+    Legacy,
+}
+
+// Shared, every copy of a list value sees changes made through the others.
+pub type List = Rc<RefCell<Vec<Value>>>;
+// Shared the same way as lists.
+pub type Map = Rc<RefCell<map::Map<Value>>>;
 
 #[derive(Clone, Debug)]
 pub enum Value {
     Bool(bool),
     Number(Number),
     String(String),
+    Function(Rc<Function>),
+    Native(Rc<NativeFunction>),
+    Class(Rc<Class>),
+    Instance(Rc<Instance>),
+    List(List),
+    Map(Map),
     Nil,
 }
+
+// Formats numbers the way Lox prints them: integral values without a fractional
+// part, everything else with the shortest digits that read back to the same value.
+// Very large and very small magnitudes switch to exponent notation, and the special
+// values are spelled out.
+pub fn format_number(n: Number) -> String {
+    if n.is_nan() {
+        return "NaN".to_string();
+    }
+    if n.is_infinite() {
+        return if n > 0.0 { "Infinity" } else { "-Infinity" }.to_string();
+    }
+
+    let magnitude = n.abs();
+    if magnitude >= 1e21 || (magnitude != 0.0 && magnitude < 1e-6) {
+        format!("{:e}", n)
+    } else {
+        n.to_string()
+    }
+}
+
+// Writes a string the way it would appear in source, for strings inside lists and
+// maps: `["1", 1]` would otherwise print as `[1, 1]`.
+pub fn quote(s: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\t' => quoted.push_str("\\t"),
+            '\r' => quoted.push_str("\\r"),
+            '\0' => quoted.push_str("\\0"),
+            '$' if chars.peek() == Some(&'{') => quoted.push_str("\\$"),
+            c if c.is_control() => quoted.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+// Position of `index` in a list of `len` elements, negative indices count from the
+// end. With `allow_end` the position right after the last element is valid too.
+pub fn list_index(index: Number, len: usize, allow_end: bool) -> Result<usize, RuntimeErrorKind> {
+    if index.fract() != 0.0 || !index.is_finite() {
+        return Err(RuntimeErrorKind::IndexMustBeInteger);
+    }
+
+    let index = index as i64;
+    let position = if index < 0 { index + len as i64 } else { index };
+    let limit = if allow_end { len + 1 } else { len };
+    if position < 0 || position >= limit as i64 {
+        return Err(RuntimeErrorKind::IndexOutOfBounds { index, len });
+    }
+    Ok(position as usize)
+}
+
+impl Value {
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            Value::Bool(_) => "bool",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Function(_) | Value::Native(_) => "function",
+            Value::Class(_) => "class",
+            Value::Instance(_) => "instance",
+            Value::List(_) => "list",
+            Value::Map(_) => "map",
+            Value::Nil => "nil",
+        }
+    }
+
+    pub fn to_key(&self) -> Result<Key, RuntimeErrorKind> {
+        match self {
+            Value::Nil => Ok(Key::Nil),
+            Value::Bool(b) => Ok(Key::Bool(*b)),
+            Value::Number(n) => Key::number(*n),
+            Value::String(s) => Ok(Key::String(s.as_str().into())),
+            value => Err(RuntimeErrorKind::UnhashableKey(value.type_name())),
+        }
+    }
+}
+
+impl From<&Key> for Value {
+    fn from(key: &Key) -> Value {
+        match key {
+            Key::Nil => Value::Nil,
+            Key::Bool(b) => Value::Bool(*b),
+            Key::Number(bits) => Value::Number(Number::from_bits(*bits)),
+            Key::String(s) => Value::String(s.to_string()),
+        }
+    }
+}
+
+impl From<bool> for Value {
+    fn from(b: bool) -> Value {
+        Value::Bool(b)
+    }
+}
+
+impl From<Number> for Value {
+    fn from(n: Number) -> Value {
+        Value::Number(n)
+    }
+}
+
+impl From<String> for Value {
+    fn from(s: String) -> Value {
+        Value::String(s)
+    }
+}
+
+impl From<&str> for Value {
+    fn from(s: &str) -> Value {
+        Value::String(s.to_string())
+    }
+}
+
+impl From<()> for Value {
+    fn from(_: ()) -> Value {
+        Value::Nil
+    }
+}
@@ -0,0 +1,788 @@
+use std::{
+    collections::HashMap,
+    io::{BufRead, Write},
+    rc::Rc,
+};
+
+use crate::{
+    chunk::{Constant, FunctionProto, OpCode},
+    error::{RuntimeError, RuntimeErrorKind, TraceFrame},
+    map::Map,
+    natives::{self, Io},
+    object::{
+        BoundMethod, Class, Closure, GcConfig, Heap, HeapStats, Instance, ObjRef, Object, Upvalue,
+        Value,
+    },
+    token::Span,
+    value::{self, Dialect, Number, MAX_FRAMES},
+};
+
+type Result<T> = std::result::Result<T, RuntimeError>;
+
+// Stack machine executing chunks produced by the `Compiler`. Globals survive between
+// calls to `interpret`.
+pub struct Vm {
+    stack: Vec<Value>,
+    frames: Vec<CallFrame>,
+    globals: HashMap<Rc<str>, Value>,
+    // Upvalues that still point into the stack.
+    open_upvalues: Vec<ObjRef>,
+    heap: Heap,
+    dialect: Dialect,
+    io: Io,
+}
+
+struct CallFrame {
+    closure: ObjRef,
+    function: Rc<FunctionProto>,
+    ip: usize,
+    // Stack index of the callee, arguments and locals follow it.
+    base: usize,
+    // Running `init` on behalf of a class call, traces name the class.
+    is_constructor: bool,
+}
+
+impl Vm {
+    pub fn new() -> Vm {
+        Vm::with_gc(GcConfig::default())
+    }
+
+    pub fn with_gc(config: GcConfig) -> Vm {
+        let mut vm = Vm {
+            stack: Vec::with_capacity(256),
+            frames: Vec::with_capacity(64),
+            globals: HashMap::new(),
+            open_upvalues: vec![],
+            heap: Heap::with_config(config),
+            dialect: Dialect::default(),
+            io: Io::default(),
+        };
+        for native in natives::VM_CORE {
+            let obj = vm.alloc(Object::Native(native));
+            vm.globals.insert(native.name.into(), Value::Object(obj));
+        }
+        vm
+    }
+
+    // VM that prints to `output` and lets `input()` read lines from `input`.
+    pub fn with_io(output: impl Write + 'static, input: impl BufRead + 'static) -> Vm {
+        let mut vm = Vm::new();
+        vm.io = Io::new(output, input);
+        vm
+    }
+
+    pub fn set_dialect(&mut self, dialect: Dialect) {
+        self.dialect = dialect;
+    }
+
+    pub fn heap_stats(&self) -> HeapStats {
+        self.heap.stats()
+    }
+
+    pub fn interpret(&mut self, function: Rc<FunctionProto>) -> Result<()> {
+        let closure = self.alloc(Object::Closure(Closure {
+            function: function.clone(),
+            upvalues: vec![],
+        }));
+        self.stack.push(Value::Object(closure));
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            base: 0,
+            is_constructor: false,
+        });
+
+        let res = self.run().map_err(|err| self.with_trace(err));
+        if res.is_err() {
+            // Closures that escaped into globals keep working in the next call.
+            self.close_upvalues(0);
+            self.stack.clear();
+            self.frames.clear();
+            self.open_upvalues.clear();
+        }
+        res
+    }
+
+    fn run(&mut self) -> Result<()> {
+        loop {
+            let byte = self.read_byte();
+            let Some(code) = OpCode::from_byte(byte) else {
+                unreachable!("Unknown opcode {}", byte)
+            };
+
+            match code {
+                OpCode::Constant => {
+                    let value = match self.read_constant() {
+                        Constant::Number(n) => Value::Number(n),
+                        Constant::String(s) => Value::String(s),
+                        Constant::Function(_) => unreachable!("functions are loaded by Closure"),
+                    };
+                    self.stack.push(value);
+                }
+                OpCode::Nil => self.stack.push(Value::Nil),
+                OpCode::True => self.stack.push(Value::Bool(true)),
+                OpCode::False => self.stack.push(Value::Bool(false)),
+                OpCode::Uninitialized => self.stack.push(Value::Uninitialized),
+                OpCode::Pop => {
+                    self.stack.pop();
+                }
+                OpCode::GetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let value = self.stack[self.frame().base + slot].clone();
+                    self.stack.push(value);
+                }
+                OpCode::GetLocalChecked => {
+                    let slot = self.read_byte() as usize;
+                    let name = self.read_string();
+                    let value = self.stack[self.frame().base + slot].clone();
+                    self.push_initialized(value, name)?;
+                }
+                OpCode::SetLocal => {
+                    let slot = self.read_byte() as usize;
+                    let base = self.frame().base;
+                    self.stack[base + slot] = self.peek(0).clone();
+                }
+                OpCode::GetGlobal => {
+                    let name = self.read_string();
+                    let Some(value) = self.globals.get(&name) else {
+                        return Err(
+                            self.error(RuntimeErrorKind::UndefinedVariable(name.to_string()))
+                        );
+                    };
+                    let value = value.clone();
+                    self.push_initialized(value, name)?;
+                }
+                OpCode::DefineGlobal => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    self.globals.insert(name, value);
+                }
+                OpCode::SetGlobal => {
+                    let name = self.read_string();
+                    let value = self.peek(0).clone();
+                    match self.globals.get_mut(&name) {
+                        Some(global) => *global = value,
+                        None => {
+                            return Err(
+                                self.error(RuntimeErrorKind::UndefinedVariable(name.to_string()))
+                            )
+                        }
+                    }
+                }
+                OpCode::GetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let value = self.upvalue(index);
+                    self.stack.push(value);
+                }
+                OpCode::GetUpvalueChecked => {
+                    let index = self.read_byte() as usize;
+                    let name = self.read_string();
+                    let value = self.upvalue(index);
+                    self.push_initialized(value, name)?;
+                }
+                OpCode::SetUpvalue => {
+                    let index = self.read_byte() as usize;
+                    let value = self.peek(0).clone();
+                    let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+                    match self.heap.upvalue_mut(upvalue) {
+                        Upvalue::Open(slot) => {
+                            let slot = *slot;
+                            self.stack[slot] = value;
+                        }
+                        Upvalue::Closed(closed) => *closed = value,
+                    }
+                }
+                OpCode::GetProperty => {
+                    let name = self.read_string();
+                    let instance = match self.peek(0) {
+                        Value::Object(obj) => match self.heap.get(*obj) {
+                            Object::Instance(instance) => Some(instance),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let Some(instance) = instance else {
+                        return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveProperties));
+                    };
+
+                    // Fields shadow methods.
+                    let field = instance.fields.get(&name).cloned();
+                    let class = instance.class;
+                    let receiver = self.pop();
+                    match field {
+                        Some(value) => self.stack.push(value),
+                        None => self.bind_method(class, receiver, name)?,
+                    }
+                }
+                OpCode::SetProperty => {
+                    let name = self.read_string();
+                    let value = self.pop();
+                    let object = self.pop();
+                    let Value::Object(obj) = object else {
+                        return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveFields));
+                    };
+                    let Object::Instance(instance) = self.heap.get_mut(obj) else {
+                        return Err(self.error(RuntimeErrorKind::OnlyInstancesHaveFields));
+                    };
+                    instance.fields.insert(name, value.clone());
+                    self.stack.push(value);
+                }
+                OpCode::GetSuper => {
+                    let name = self.read_string();
+                    let superclass = self.pop();
+                    let superclass = self.class_operand(&superclass, "'super' must be a class")?;
+                    let receiver = self.pop();
+                    self.bind_method(superclass, receiver, name)?;
+                }
+                OpCode::Equal if self.dialect == Dialect::Legacy => self.compare(|a, b| a == b)?,
+                OpCode::NotEqual if self.dialect == Dialect::Legacy => {
+                    self.compare(|a, b| a != b)?
+                }
+                OpCode::Equal => {
+                    let equal = is_equal(self.peek(1), self.peek(0));
+                    self.replace_operands(Value::Bool(equal));
+                }
+                OpCode::NotEqual => {
+                    let equal = is_equal(self.peek(1), self.peek(0));
+                    self.replace_operands(Value::Bool(!equal));
+                }
+                OpCode::Greater => self.compare(|a, b| a > b)?,
+                OpCode::GreaterEqual => self.compare(|a, b| a >= b)?,
+                OpCode::Less => self.compare(|a, b| a < b)?,
+                OpCode::LessEqual => self.compare(|a, b| a <= b)?,
+                OpCode::Add => match (self.peek(1), self.peek(0)) {
+                    (Value::Number(a), Value::Number(b)) => {
+                        let sum = a + b;
+                        self.replace_operands(Value::Number(sum));
+                    }
+                    (Value::String(a), Value::String(b)) => {
+                        let joined: Rc<str> = Rc::from(format!("{}{}", a, b));
+                        self.replace_operands(Value::String(joined));
+                    }
+                    _ => {
+                        return Err(self.error(RuntimeErrorKind::OperandsMustBeNumbersOrStrings));
+                    }
+                },
+                OpCode::Subtract => self.arithmetic(|a, b| a - b)?,
+                OpCode::Multiply => self.arithmetic(|a, b| a * b)?,
+                OpCode::Divide => self.arithmetic(|a, b| a / b)?,
+                OpCode::Not => {
+                    let value = match self.peek(0) {
+                        Value::Bool(b) => Value::Bool(!b),
+                        value if self.dialect == Dialect::Lox => {
+                            Value::Bool(!is_truthy(value, self.dialect))
+                        }
+                        _ => return Err(self.error(RuntimeErrorKind::OperandMustBeBoolean)),
+                    };
+                    *self.stack.last_mut().expect("operand on the stack") = value;
+                }
+                OpCode::Negate => {
+                    let Value::Number(n) = self.peek(0) else {
+                        return Err(self.error(RuntimeErrorKind::OperandMustBeNumber));
+                    };
+                    let value = Value::Number(-n);
+                    *self.stack.last_mut().expect("operand on the stack") = value;
+                }
+                OpCode::Stringify => {
+                    if !matches!(self.peek(0), Value::String(_)) {
+                        let value = self.pop();
+                        let string = self.heap.stringify(&value);
+                        self.stack.push(Value::String(string.into()));
+                    }
+                }
+                OpCode::Print => {
+                    let value = self.pop();
+                    let text = self.heap.stringify(&value);
+                    if let Err(err) = writeln!(self.io.output, "{}", text) {
+                        return Err(self.error(RuntimeErrorKind::Io(err.to_string())));
+                    }
+                }
+                OpCode::Jump => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip += offset;
+                }
+                OpCode::JumpIfFalse => {
+                    let offset = self.read_u16() as usize;
+                    if !is_truthy(self.peek(0), self.dialect) {
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                // Only legacy `if` statements skip non-boolean conditions, the bytecode
+                // is the same for both dialects.
+                OpCode::JumpIfNotBool => {
+                    let offset = self.read_u16() as usize;
+                    if self.dialect == Dialect::Legacy && !matches!(self.peek(0), Value::Bool(_)) {
+                        self.pop();
+                        self.frame_mut().ip += offset;
+                    }
+                }
+                OpCode::Loop => {
+                    let offset = self.read_u16() as usize;
+                    self.frame_mut().ip -= offset;
+                }
+                OpCode::Call => {
+                    let argc = self.read_byte() as usize;
+                    self.call_value(self.peek(argc).clone(), argc)?;
+                }
+                OpCode::Closure => {
+                    let Constant::Function(function) = self.read_constant() else {
+                        unreachable!("Closure takes a function constant")
+                    };
+
+                    let mut upvalues = Vec::with_capacity(function.upvalue_count);
+                    for _ in 0..function.upvalue_count {
+                        let is_local = self.read_byte() == 1;
+                        let index = self.read_byte() as usize;
+                        let upvalue = if is_local {
+                            self.capture_upvalue(self.frame().base + index)
+                        } else {
+                            self.heap.closure(self.frame().closure).upvalues[index]
+                        };
+                        upvalues.push(upvalue);
+                    }
+
+                    let closure = self.alloc(Object::Closure(Closure { function, upvalues }));
+                    self.stack.push(Value::Object(closure));
+                }
+                OpCode::CloseUpvalue => {
+                    self.close_upvalues(self.stack.len() - 1);
+                    self.pop();
+                }
+                OpCode::Return => {
+                    let result = self.pop();
+                    let frame = self.frames.pop().expect("a frame is active");
+                    self.close_upvalues(frame.base);
+                    self.stack.truncate(frame.base);
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.push(result);
+                }
+                OpCode::Class => {
+                    let name = self.read_string();
+                    let class = self.alloc(Object::Class(Class {
+                        name,
+                        methods: HashMap::new(),
+                    }));
+                    self.stack.push(Value::Object(class));
+                }
+                OpCode::Inherit => {
+                    let superclass = match self.peek(1) {
+                        Value::Object(obj) => match self.heap.get(*obj) {
+                            Object::Class(class) => Some(class.methods.clone()),
+                            _ => None,
+                        },
+                        _ => None,
+                    };
+                    let Some(methods) = superclass else {
+                        return Err(self.error(RuntimeErrorKind::SuperclassMustBeClass));
+                    };
+
+                    let subclass = self.pop();
+                    let subclass = self.class_operand(&subclass, "only classes can inherit")?;
+                    self.heap.class_mut(subclass).methods.extend(methods);
+                }
+                OpCode::BuildList => {
+                    let count = self.read_u16() as usize;
+                    let items = self.stack.split_off(self.stack.len() - count);
+                    let list = self.alloc(Object::List(items));
+                    self.stack.push(Value::Object(list));
+                }
+                OpCode::BuildMap => {
+                    let count = self.read_u16() as usize;
+                    let pairs = self.stack.split_off(self.stack.len() - 2 * count);
+                    let mut map = Map::new();
+                    for pair in pairs.chunks_exact(2) {
+                        let key = self.heap.key(&pair[0]).map_err(|kind| self.error(kind))?;
+                        map.insert(key, pair[1].clone());
+                    }
+                    let map = self.alloc(Object::Map(map));
+                    self.stack.push(Value::Object(map));
+                }
+                OpCode::GetIndex => {
+                    let value = self.get_index(self.peek(1), self.peek(0))?;
+                    self.replace_operands(value);
+                }
+                OpCode::SetIndex => {
+                    let value = self.pop();
+                    let (object, index) = (self.peek(1).clone(), self.peek(0).clone());
+                    self.set_index(&object, &index, value.clone())?;
+                    self.replace_operands(value);
+                }
+                OpCode::Method => {
+                    let name = self.read_string();
+                    let method = match self.pop() {
+                        Value::Object(obj) if matches!(self.heap.get(obj), Object::Closure(_)) => {
+                            obj
+                        }
+                        _ => {
+                            let kind =
+                                RuntimeErrorKind::InvalidBytecode("methods must be closures");
+                            return Err(self.error(kind));
+                        }
+                    };
+                    let class = self.peek(0).clone();
+                    let class = self.class_operand(&class, "methods must be defined on a class")?;
+                    self.heap.class_mut(class).methods.insert(name, method);
+                }
+            }
+        }
+    }
+
+    fn call_value(&mut self, callee: Value, argc: usize) -> Result<()> {
+        let Value::Object(obj) = callee else {
+            return Err(self.error(RuntimeErrorKind::NotCallable));
+        };
+
+        match self.heap.get(obj) {
+            Object::Closure(_) => self.call(obj, argc, false),
+            Object::BoundMethod(bound) => {
+                let method = bound.method;
+                let callee_slot = self.stack.len() - argc - 1;
+                self.stack[callee_slot] = bound.receiver.clone();
+                self.call(method, argc, false)
+            }
+            Object::Class(class) => {
+                let init = class.methods.get("init").copied();
+                if init.is_none() && argc != 0 {
+                    return Err(self.error(RuntimeErrorKind::ArityMismatch {
+                        expected: 0,
+                        got: argc,
+                    }));
+                }
+
+                let instance = self.alloc(Object::Instance(Instance {
+                    class: obj,
+                    fields: HashMap::new(),
+                }));
+                let callee_slot = self.stack.len() - argc - 1;
+                self.stack[callee_slot] = Value::Object(instance);
+                match init {
+                    Some(init) => self.call(init, argc, true),
+                    None => Ok(()),
+                }
+            }
+            Object::Native(native) => {
+                let native = *native;
+                if argc != native.arity {
+                    return Err(self.error(RuntimeErrorKind::ArityMismatch {
+                        expected: native.arity,
+                        got: argc,
+                    }));
+                }
+
+                let args = self.stack.split_off(self.stack.len() - argc);
+                let result = (native.function)(&mut self.heap, &mut self.io, &args)
+                    .map_err(|kind| self.error(kind))?;
+                self.pop();
+                self.stack.push(result);
+                Ok(())
+            }
+            Object::Instance(_) | Object::Upvalue(_) | Object::List(_) | Object::Map(_) => {
+                Err(self.error(RuntimeErrorKind::NotCallable))
+            }
+        }
+    }
+
+    fn call(&mut self, closure: ObjRef, argc: usize, is_constructor: bool) -> Result<()> {
+        let function = self.heap.closure(closure).function.clone();
+        if argc != function.arity {
+            return Err(self.error(RuntimeErrorKind::ArityMismatch {
+                expected: function.arity,
+                got: argc,
+            }));
+        }
+        if self.frames.len() >= MAX_FRAMES {
+            return Err(self.error(RuntimeErrorKind::StackOverflow));
+        }
+
+        self.frames.push(CallFrame {
+            closure,
+            function,
+            ip: 0,
+            base: self.stack.len() - argc - 1,
+            is_constructor,
+        });
+        Ok(())
+    }
+
+    // Class the compiler guarantees to be on the stack, which a hand-made bytecode file
+    // may replace with anything.
+    fn class_operand(&self, value: &Value, reason: &'static str) -> Result<ObjRef> {
+        match value {
+            Value::Object(obj) if matches!(self.heap.get(*obj), Object::Class(_)) => Ok(*obj),
+            _ => Err(self.error(RuntimeErrorKind::InvalidBytecode(reason))),
+        }
+    }
+
+    fn bind_method(&mut self, class: ObjRef, receiver: Value, name: Rc<str>) -> Result<()> {
+        let Some(&method) = self.heap.class(class).methods.get(&name) else {
+            return Err(self.error(RuntimeErrorKind::UndefinedProperty(name.to_string())));
+        };
+
+        let bound = self.alloc(Object::BoundMethod(BoundMethod { receiver, method }));
+        self.stack.push(Value::Object(bound));
+        Ok(())
+    }
+
+    // Every allocation may collect first. Objects the VM is holding outside its roots
+    // have to be reachable from the object being allocated.
+    fn alloc(&mut self, object: Object) -> ObjRef {
+        if self.heap.should_collect() {
+            self.collect_garbage(&object);
+        }
+        self.heap.alloc(object)
+    }
+
+    fn collect_garbage(&mut self, pending: &Object) {
+        for value in &self.stack {
+            self.heap.mark_value(value);
+        }
+        for frame in &self.frames {
+            self.heap.mark(frame.closure);
+        }
+        for &upvalue in &self.open_upvalues {
+            self.heap.mark(upvalue);
+        }
+        for value in self.globals.values() {
+            self.heap.mark_value(value);
+        }
+        self.heap.mark_references(pending);
+        self.heap.collect();
+    }
+
+    fn capture_upvalue(&mut self, slot: usize) -> ObjRef {
+        let heap = &self.heap;
+        let existing = self.open_upvalues.iter().copied().find(|&upvalue| {
+            matches!(heap.get(upvalue), Object::Upvalue(Upvalue::Open(open)) if *open == slot)
+        });
+        if let Some(upvalue) = existing {
+            return upvalue;
+        }
+
+        let upvalue = self.alloc(Object::Upvalue(Upvalue::Open(slot)));
+        self.open_upvalues.push(upvalue);
+        upvalue
+    }
+
+    // Moves every captured variable living at or above `from` off the stack.
+    fn close_upvalues(&mut self, from: usize) {
+        let stack = &self.stack;
+        let heap = &mut self.heap;
+        self.open_upvalues.retain(|&upvalue| {
+            let upvalue = heap.upvalue_mut(upvalue);
+            match *upvalue {
+                Upvalue::Open(slot) if slot >= from => {
+                    *upvalue = Upvalue::Closed(stack[slot].clone());
+                    false
+                }
+                _ => true,
+            }
+        });
+    }
+
+    fn upvalue(&mut self, index: usize) -> Value {
+        let upvalue = self.heap.closure(self.frame().closure).upvalues[index];
+        match self.heap.upvalue_mut(upvalue) {
+            Upvalue::Open(slot) => self.stack[*slot].clone(),
+            Upvalue::Closed(value) => value.clone(),
+        }
+    }
+
+    fn push_initialized(&mut self, value: Value, name: Rc<str>) -> Result<()> {
+        if let Value::Uninitialized = value {
+            return Err(self.error(RuntimeErrorKind::UninitializedVariable(name.to_string())));
+        }
+        self.stack.push(value);
+        Ok(())
+    }
+
+    fn arithmetic(&mut self, f: fn(Number, Number) -> Number) -> Result<()> {
+        let (a, b) = self.number_operands()?;
+        self.replace_operands(Value::Number(f(a, b)));
+        Ok(())
+    }
+
+    fn compare(&mut self, f: fn(Number, Number) -> bool) -> Result<()> {
+        let (a, b) = self.number_operands()?;
+        self.replace_operands(Value::Bool(f(a, b)));
+        Ok(())
+    }
+
+    fn number_operands(&self) -> Result<(Number, Number)> {
+        match (self.peek(1), self.peek(0)) {
+            (Value::Number(a), Value::Number(b)) => Ok((*a, *b)),
+            _ => Err(self.error(RuntimeErrorKind::OperandsMustBeNumbers)),
+        }
+    }
+
+    // Pops both operands of a binary instruction and pushes its result.
+    fn replace_operands(&mut self, value: Value) {
+        self.stack.pop();
+        *self.stack.last_mut().expect("operand on the stack") = value;
+    }
+
+    fn get_index(&self, object: &Value, index: &Value) -> Result<Value> {
+        if let Some(Object::Map(map)) = self.object(object) {
+            let key = self.heap.key(index).map_err(|kind| self.error(kind))?;
+            return map
+                .get(&key)
+                .cloned()
+                .ok_or_else(|| self.error(RuntimeErrorKind::UndefinedKey(key.to_string())));
+        }
+
+        let (list, position) = self.list_element(object, index)?;
+        let Object::List(items) = self.heap.get(list) else {
+            unreachable!("list_element returns a list")
+        };
+        Ok(items[position].clone())
+    }
+
+    fn set_index(&mut self, object: &Value, index: &Value, value: Value) -> Result<()> {
+        if let Some(Object::Map(_)) = self.object(object) {
+            let key = self.heap.key(index).map_err(|kind| self.error(kind))?;
+            let Value::Object(map) = object else {
+                unreachable!("maps live on the heap")
+            };
+            self.heap.map_mut(*map).insert(key, value);
+            return Ok(());
+        }
+
+        let (list, position) = self.list_element(object, index)?;
+        self.heap.list_mut(list)[position] = value;
+        Ok(())
+    }
+
+    fn object(&self, value: &Value) -> Option<&Object> {
+        match value {
+            Value::Object(obj) => Some(self.heap.get(*obj)),
+            _ => None,
+        }
+    }
+
+    fn list_element(&self, object: &Value, index: &Value) -> Result<(ObjRef, usize)> {
+        let list = match object {
+            Value::Object(obj) => match self.heap.get(*obj) {
+                Object::List(items) => Some((*obj, items.len())),
+                _ => None,
+            },
+            _ => None,
+        };
+        let Some((list, len)) = list else {
+            return Err(self.error(RuntimeErrorKind::NotIndexable));
+        };
+        let Value::Number(index) = index else {
+            return Err(self.error(RuntimeErrorKind::IndexMustBeInteger));
+        };
+
+        let position = value::list_index(*index, len, false).map_err(|kind| self.error(kind))?;
+        Ok((list, position))
+    }
+
+    fn read_byte(&mut self) -> u8 {
+        let frame = self.frame_mut();
+        let byte = frame.function.chunk.code[frame.ip];
+        frame.ip += 1;
+        byte
+    }
+
+    fn read_u16(&mut self) -> u16 {
+        let frame = self.frame_mut();
+        let value = frame.function.chunk.read_u16(frame.ip);
+        frame.ip += 2;
+        value
+    }
+
+    fn read_constant(&mut self) -> Constant {
+        let index = self.read_u16() as usize;
+        self.frame().function.chunk.constants[index].clone()
+    }
+
+    fn read_string(&mut self) -> Rc<str> {
+        match self.read_constant() {
+            Constant::String(s) => s,
+            constant => unreachable!("Expected a name, found {}", constant),
+        }
+    }
+
+    fn peek(&self, distance: usize) -> &Value {
+        &self.stack[self.stack.len() - 1 - distance]
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().expect("value on the stack")
+    }
+
+    fn frame(&self) -> &CallFrame {
+        self.frames.last().expect("a frame is active")
+    }
+
+    fn frame_mut(&mut self) -> &mut CallFrame {
+        self.frames.last_mut().expect("a frame is active")
+    }
+
+    // Error at the instruction currently being executed.
+    fn error(&self, kind: RuntimeErrorKind) -> RuntimeError {
+        RuntimeError::at(self.current_span(0), kind)
+    }
+
+    fn current_span(&self, depth: usize) -> Span {
+        let frame = &self.frames[self.frames.len() - 1 - depth];
+        frame.function.chunk.span_at(frame.ip.saturating_sub(1))
+    }
+
+    // Records every active call, innermost first, with the line it was called from.
+    fn with_trace(&self, mut err: RuntimeError) -> RuntimeError {
+        for depth in 0..self.frames.len().saturating_sub(1) {
+            let frame = &self.frames[self.frames.len() - 1 - depth];
+            let function = if frame.is_constructor {
+                match &self.stack[frame.base] {
+                    Value::Object(instance) => match self.heap.get(*instance) {
+                        Object::Instance(instance) => {
+                            self.heap.class(instance.class).name.to_string()
+                        }
+                        _ => frame.function.name.clone(),
+                    },
+                    _ => frame.function.name.clone(),
+                }
+            } else {
+                frame.function.name.clone()
+            };
+
+            err.trace.push(TraceFrame {
+                function,
+                line: self.current_span(depth + 1).line,
+            });
+        }
+        err
+    }
+}
+
+impl Default for Vm {
+    fn default() -> Self {
+        Vm::new()
+    }
+}
+
+fn is_truthy(value: &Value, dialect: Dialect) -> bool {
+    match value {
+        Value::Bool(b) => *b,
+        Value::Nil | Value::Uninitialized => false,
+        Value::Number(n) if dialect == Dialect::Legacy => *n != 0.0,
+        Value::String(s) if dialect == Dialect::Legacy => !s.is_empty(),
+        _ => true,
+    }
+}
+
+// Strings compare by content, heap objects by identity.
+fn is_equal(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Nil, Value::Nil) => true,
+        (Value::Bool(a), Value::Bool(b)) => a == b,
+        (Value::Number(a), Value::Number(b)) => a == b,
+        (Value::String(a), Value::String(b)) => a == b,
+        (Value::Object(a), Value::Object(b)) => a == b,
+        _ => false,
+    }
+}
@@ -0,0 +1,314 @@
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{compile, jlox};
+use jlox::{
+    chunk::{Constant, FunctionProto, OpCode},
+    disassembler,
+    error::BytecodeError,
+    serialize::{self, FORMAT_VERSION},
+    token::Span,
+};
+
+fn path(name: &str) -> String {
+    PathBuf::from(env!("CARGO_TARGET_TMPDIR"))
+        .join(name)
+        .to_string_lossy()
+        .into_owned()
+}
+
+#[test]
+fn round_trip_preserves_the_program() {
+    let function = compile(
+        r#"class Greeter {
+    init(name) { this.name = name; }
+    greet() { return "hello " + this.name; }
+}
+fun twice(f) { fun g(x) { return f(f(x)); } return g; }
+var i = 0.5;
+while (i < 10) i = twice(twice)(i) * 2;
+print Greeter("lox").greet();
+"#,
+    );
+
+    let bytes = serialize::serialize(&function);
+    let loaded = serialize::deserialize(&bytes).unwrap();
+    assert_eq!(
+        disassembler::disassemble(&loaded),
+        disassembler::disassemble(&function)
+    );
+}
+
+#[test]
+fn compile_then_run() {
+    let source = path("compile_then_run.lox");
+    let output = path("compile_then_run.loxc");
+    fs::write(
+        &source,
+        "fun add(a, b) { return a + b; }\nprint add(1, 2);\nadd(1, nil);\n",
+    )
+    .unwrap();
+
+    let compiled = jlox(&["compile", &source, "-o", &output]);
+    assert_eq!(compiled.stdout, "");
+    assert_eq!(compiled.stderr, "");
+    assert_eq!(compiled.code, Some(0));
+
+    // The source is gone, errors still point at the right place.
+    fs::remove_file(&source).unwrap();
+    let run = jlox(&[&output]);
+    assert_eq!(run.stdout, "3\n");
+    assert_eq!(run.code, Some(70));
+    assert_eq!(
+        run.stderr,
+        "runtime error: Operands must be two numbers or two strings\n --> line 1, column 26\n  = help: '+' adds two numbers or concatenates two strings\n  = in add() called from line 3\n"
+    );
+}
+
+#[test]
+fn failures_exit_with_sysexits_codes() {
+    let source = path("compile_error.lox");
+    let output = path("compile_error.loxc");
+    fs::write(&source, "print 1 +;\n").unwrap();
+    let _ = fs::remove_file(&output);
+
+    let compiled = jlox(&["compile", &source, "-o", &output]);
+    assert!(compiled.stderr.contains("Expected expression"));
+    assert_eq!(compiled.code, Some(65));
+    assert!(!PathBuf::from(&output).exists());
+
+    let mut bytes = serialize::serialize(&compile("print 1;"));
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+    fs::write(&output, bytes).unwrap();
+    let run = jlox(&[&output]);
+    assert!(run.stderr.starts_with("error: "));
+    assert_eq!(run.code, Some(65));
+}
+
+#[test]
+fn unreadable_source_or_unwritable_output_is_reported() {
+    let missing = path("missing.lox");
+    let compiled = jlox(&["compile", &missing]);
+    assert!(compiled
+        .stderr
+        .starts_with(&format!("error: {}: ", missing)));
+    assert_eq!(compiled.code, Some(66));
+
+    let source = path("unwritable.lox");
+    let output = path("no_such_dir/unwritable.loxc");
+    fs::write(&source, "print 1;\n").unwrap();
+    let compiled = jlox(&["compile", &source, "-o", &output]);
+    assert!(compiled.stderr.starts_with(&format!("error: {}: ", output)));
+    assert_eq!(compiled.code, Some(73));
+}
+
+#[test]
+fn rejects_other_versions() {
+    let mut bytes = serialize::serialize(&compile("print 1;"));
+    bytes[4..6].copy_from_slice(&(FORMAT_VERSION + 1).to_le_bytes());
+
+    assert_eq!(
+        serialize::deserialize(&bytes).unwrap_err(),
+        BytecodeError::IncompatibleVersion {
+            found: FORMAT_VERSION + 1,
+            expected: FORMAT_VERSION
+        }
+    );
+
+    let file = path("other_version.loxc");
+    fs::write(&file, &bytes).unwrap();
+    let output = jlox(&[&file]);
+    assert!(output
+        .stderr
+        .contains("Compiled with an incompatible version of jlox"));
+}
+
+#[test]
+fn rejects_corrupted_files() {
+    let bytes = serialize::serialize(&compile("print 1 + 2;"));
+
+    let mut flipped = bytes.clone();
+    flipped[12] ^= 0x40;
+    assert_eq!(
+        serialize::deserialize(&flipped).unwrap_err(),
+        BytecodeError::ChecksumMismatch
+    );
+
+    assert_eq!(
+        serialize::deserialize(&bytes[..5]).unwrap_err(),
+        BytecodeError::Truncated
+    );
+    assert_eq!(
+        serialize::deserialize(b"print 1;").unwrap_err(),
+        BytecodeError::NotBytecode
+    );
+}
+
+#[test]
+fn rejects_code_the_vm_cannot_run() {
+    let mut function = FunctionProto::default();
+    function
+        .chunk
+        .write(OpCode::Constant as u8, Span::default());
+    function.chunk.write(0, Span::default());
+    function.chunk.write(7, Span::default());
+    function.chunk.write(OpCode::Return as u8, Span::default());
+
+    let bytes = serialize::serialize(&function);
+    assert!(matches!(
+        serialize::deserialize(&bytes),
+        Err(BytecodeError::Malformed(_))
+    ));
+}
+
+#[test]
+fn rejects_code_that_misuses_the_stack() {
+    let load = |code: &[u8]| {
+        let mut function = FunctionProto::default();
+        for byte in code {
+            function.chunk.write(*byte, Span::default());
+        }
+        // `serialize` computes a valid checksum, only the verifier stands between
+        // this code and the VM.
+        serialize::deserialize(&serialize::serialize(&function))
+    };
+    let (get, pop, nil) = (OpCode::GetLocal as u8, OpCode::Pop as u8, OpCode::Nil as u8);
+    let (jump, ret) = (OpCode::Jump as u8, OpCode::Return as u8);
+
+    assert!(load(&[get, 0, ret]).is_ok());
+    for code in [
+        // The script's frame only holds the script itself.
+        &[get, 1, ret][..],
+        &[nil, OpCode::SetLocal as u8, 2, ret],
+        &[pop, pop, nil, ret],
+        &[OpCode::Add as u8, ret],
+        // Lands on the operand of `GetLocal`.
+        &[jump, 0, 1, get, ret, ret],
+        // The paths meet at `Return` with different stack heights.
+        &[nil, OpCode::JumpIfFalse as u8, 0, 1, nil, ret],
+    ] {
+        assert!(
+            matches!(load(code), Err(BytecodeError::Malformed(_))),
+            "accepted {:?}",
+            code
+        );
+    }
+
+    // The script runs without arguments, whatever it claims.
+    let mut script = FunctionProto {
+        arity: 1,
+        ..FunctionProto::default()
+    };
+    for byte in [get, 1, ret] {
+        script.chunk.write(byte, Span::default());
+    }
+    assert!(matches!(
+        serialize::deserialize(&serialize::serialize(&script)),
+        Err(BytecodeError::Malformed(_))
+    ));
+
+    let file = path("misused_stack.loxc");
+    let mut function = FunctionProto::default();
+    for byte in [get, 9, OpCode::Print as u8, nil, ret] {
+        function.chunk.write(byte, Span::default());
+    }
+    fs::write(&file, serialize::serialize(&function)).unwrap();
+    let output = jlox(&[&file]);
+    assert_eq!(
+        output.stderr,
+        format!(
+            "error: {}: Malformed bytecode: local slot 9 out of range\n",
+            file
+        )
+    );
+    assert_eq!(output.code, Some(65));
+}
+
+#[test]
+fn type_confused_code_is_a_runtime_error() {
+    let (constant, class, nil, pop, ret) = (
+        OpCode::Constant as u8,
+        OpCode::Class as u8,
+        OpCode::Nil as u8,
+        OpCode::Pop as u8,
+        OpCode::Return as u8,
+    );
+    // Constant 0 is a number, constant 1 a name.
+    for (name, code, message) in [
+        (
+            "method_on_number",
+            vec![
+                constant,
+                0,
+                0,
+                constant,
+                0,
+                0,
+                OpCode::Method as u8,
+                0,
+                1,
+                pop,
+                nil,
+                ret,
+            ],
+            "methods must be closures",
+        ),
+        (
+            "number_inherits",
+            vec![
+                class,
+                0,
+                1,
+                constant,
+                0,
+                0,
+                OpCode::Inherit as u8,
+                pop,
+                nil,
+                ret,
+            ],
+            "only classes can inherit",
+        ),
+        (
+            "super_is_number",
+            vec![
+                nil,
+                constant,
+                0,
+                0,
+                OpCode::GetSuper as u8,
+                0,
+                1,
+                pop,
+                nil,
+                ret,
+            ],
+            "'super' must be a class",
+        ),
+    ] {
+        let mut function = FunctionProto::default();
+        function.chunk.add_constant(Constant::Number(1.0));
+        function.chunk.add_constant(Constant::String("m".into()));
+        for byte in code {
+            function.chunk.write(byte, Span::default());
+        }
+
+        // Well formed as far as the verifier can tell.
+        let bytes = serialize::serialize(&function);
+        assert!(serialize::deserialize(&bytes).is_ok(), "{} rejected", name);
+
+        let file = path(&format!("{}.loxc", name));
+        fs::write(&file, bytes).unwrap();
+        let output = jlox(&[&file]);
+        assert!(
+            output
+                .stderr
+                .starts_with(&format!("runtime error: Invalid bytecode: {}\n", message)),
+            "{}: {}",
+            name,
+            output.stderr
+        );
+        assert_eq!(output.code, Some(70));
+    }
+}
@@ -0,0 +1,140 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn fields_methods_and_initializer() {
+    let source = r#"
+        class Point {
+            init(x, y) {
+                this.x = x;
+                this.y = y;
+            }
+
+            sum() {
+                return this.x + this.y;
+            }
+        }
+
+        var p = Point(1, 2);
+        print p.sum();
+        p.x = 10;
+        print p.sum();
+        print p;
+        print Point;
+    "#;
+
+    assert_eq!(
+        run("point", source).stdout,
+        "3\n12\nPoint instance\nPoint\n"
+    );
+}
+
+#[test]
+fn bound_method_keeps_receiver() {
+    let source = r#"
+        class Greeter {
+            init(name) {
+                this.name = name;
+            }
+
+            greet() {
+                print "hi " + this.name;
+            }
+        }
+
+        var greet = Greeter("bob").greet;
+        greet();
+    "#;
+
+    assert_eq!(run("bound_method", source).stdout, "hi bob\n");
+}
+
+#[test]
+fn initializer_returns_instance() {
+    let source = r#"
+        class Foo {
+            init() {
+                this.value = 1;
+                return;
+            }
+        }
+
+        var foo = Foo();
+        print foo.init();
+    "#;
+
+    assert_eq!(run("init_returns_this", source).stdout, "Foo instance\n");
+}
+
+#[test]
+fn static_errors() {
+    let output = run("this_outside_class", "print this;");
+    assert!(output
+        .stderr
+        .contains("Can't use 'this' outside of a class"));
+
+    let output = run("return_value_from_init", "class A { init() { return 1; } }");
+    assert!(output
+        .stderr
+        .contains("Can't return a value from an initializer"));
+}
+
+#[test]
+fn inheritance_and_super_calls() {
+    let source = r#"
+        class A {
+            init(n) {
+                this.n = n;
+            }
+
+            method() {
+                print "A method";
+            }
+        }
+
+        class B < A {
+            init(n) {
+                super.init(n * 2);
+            }
+
+            method() {
+                print "B method";
+            }
+
+            test() {
+                super.method();
+            }
+        }
+
+        class C < B {}
+
+        var c = C(2);
+        c.test();
+        c.method();
+        print c.n;
+    "#;
+
+    assert_eq!(run("inheritance", source).stdout, "A method\nB method\n4\n");
+}
+
+#[test]
+fn inheritance_errors() {
+    let output = run("inherit_self", "class A < A {}");
+    assert!(output.stderr.contains("A class can't inherit from itself"));
+
+    let output = run("super_outside_class", "super.method();");
+    assert!(output
+        .stderr
+        .contains("Can't use 'super' outside of a class"));
+
+    let output = run("super_without_superclass", "class A { m() { super.m(); } }");
+    assert!(output
+        .stderr
+        .contains("Can't use 'super' in a class with no superclass"));
+
+    let output = run("inherit_non_class", "var A = 1; class B < A {}");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Superclass must be a class\n --> line 1, column 22"));
+}
@@ -0,0 +1,117 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn make_counter() {
+    let source = r#"
+        fun makeCounter() {
+            var i = 0;
+            fun count() {
+                i = i + 1;
+                print i;
+            }
+            return count;
+        }
+
+        var counter = makeCounter();
+        counter();
+        counter();
+
+        var other = makeCounter();
+        other();
+        counter();
+    "#;
+
+    assert_eq!(run("make_counter", source).stdout, "1\n2\n1\n3\n");
+}
+
+#[test]
+fn closure_sees_defining_scope_not_caller() {
+    let source = r#"
+        var x = "global";
+        fun outer() {
+            var x = "outer";
+            fun inner() {
+                print x;
+            }
+            return inner;
+        }
+
+        fun caller(f) {
+            var x = "caller";
+            f();
+        }
+
+        caller(outer());
+    "#;
+
+    assert_eq!(run("defining_scope", source).stdout, "outer\n");
+}
+
+#[test]
+fn local_shadows_captured_variable() {
+    let source = r#"
+        var name = "global";
+        fun make() {
+            var name = "captured";
+            fun get() {
+                return name;
+            }
+            return get;
+        }
+
+        var get = make();
+        {
+            var name = "shadow";
+            print get();
+            print name;
+        }
+        print name;
+    "#;
+
+    assert_eq!(
+        run("shadowing", source).stdout,
+        "captured\nshadow\nglobal\n"
+    );
+}
+
+#[test]
+fn closures_share_captured_variable() {
+    let source = r#"
+        var get;
+        var set;
+        fun pair() {
+            var value = "initial";
+            fun g() { return value; }
+            fun s(v) { value = v; }
+            get = g;
+            set = s;
+        }
+
+        pair();
+        print get();
+        set("updated");
+        print get();
+    "#;
+
+    assert_eq!(run("shared_capture", source).stdout, "initial\nupdated\n");
+}
+
+#[test]
+fn closure_ignores_later_shadowing_declaration() {
+    let source = r#"
+        var a = "global";
+        {
+            fun showA() {
+                print a;
+            }
+
+            showA();
+            var a = "block";
+            showA();
+        }
+    "#;
+
+    assert_eq!(run("later_shadowing", source).stdout, "global\nglobal\n");
+}
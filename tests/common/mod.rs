@@ -0,0 +1,83 @@
+// Shared by several integration test crates, each of which uses only part of it.
+#![allow(dead_code)]
+
+use std::{fs, path::PathBuf, process::Command, rc::Rc};
+
+use jlox::{
+    chunk::FunctionProto, compiler::Compiler, error::RuntimeError, interpreter::Interpreter,
+    parser::Parser, resolver::Resolver, scanner::Scanner, statement::Statement,
+};
+
+pub struct Output {
+    pub stdout: String,
+    pub stderr: String,
+    pub code: Option<i32>,
+}
+
+// Scans, parses and resolves a program that is expected to be valid.
+pub fn parse(source: &str) -> Vec<Statement> {
+    let mut scanner = Scanner::new(source.chars().collect());
+    scanner.scan().unwrap();
+    let stmts = Parser::new(scanner.tokens()).parse().unwrap();
+    Resolver::new().resolve(&stmts).unwrap();
+    stmts
+}
+
+pub fn interpret(interpreter: &mut Interpreter, source: &str) -> Result<(), RuntimeError> {
+    interpreter.interpret_statements(parse(source))
+}
+
+pub fn compile(source: &str) -> Rc<FunctionProto> {
+    Compiler::new().compile(&parse(source)).unwrap()
+}
+
+// Runs the program on both backends, which must agree on everything they print. The
+// VM runs a second time collecting garbage before every allocation.
+pub fn run(name: &str, source: &str) -> Output {
+    run_with(&[], name, source)
+}
+
+// Like `run`, with extra command line flags passed to every backend.
+pub fn run_with(flags: &[&str], name: &str, source: &str) -> Output {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join(format!("{}.lox", name));
+    fs::write(&path, source).unwrap();
+    let path = path.to_str().unwrap();
+    let with_flags = |backend: &[&'static str]| -> Vec<&str> {
+        flags
+            .iter()
+            .chain(backend)
+            .chain([&path])
+            .copied()
+            .collect()
+    };
+
+    let output = jlox(&with_flags(&[]));
+    let vm_output = jlox(&with_flags(&["--vm"]));
+    assert_eq!(output.stdout, vm_output.stdout, "stdout differs on the VM");
+    assert_eq!(output.stderr, vm_output.stderr, "stderr differs on the VM");
+    assert_eq!(output.code, vm_output.code, "exit code differs on the VM");
+    let gc_output = jlox(&with_flags(&["--gc-stress"]));
+    assert_eq!(
+        output.stdout, gc_output.stdout,
+        "stdout differs under GC stress"
+    );
+    assert_eq!(
+        output.stderr, gc_output.stderr,
+        "stderr differs under GC stress"
+    );
+
+    output
+}
+
+pub fn jlox(args: &[&str]) -> Output {
+    let output = Command::new(env!("CARGO_BIN_EXE_jlox"))
+        .args(args)
+        .output()
+        .unwrap();
+
+    Output {
+        stdout: String::from_utf8(output.stdout).unwrap(),
+        stderr: String::from_utf8(output.stderr).unwrap(),
+        code: output.status.code(),
+    }
+}
@@ -0,0 +1,55 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn conversions_and_introspection() {
+    let source = r#"print type(clock()) + " " + type(nil) + " " + type(len);
+class Point {}
+print type(Point) + " " + type(Point());
+print str(1.5) + str(true) + str(Point);
+print num("  42 ") + 1;
+print len("héllo") + len("");
+print input();
+print clock;
+"#;
+
+    let output = run("natives_conversions_and_introspection", source);
+    assert_eq!(
+        output.stdout,
+        "number nil function\nclass instance\n1.5truePoint\n43\n5\nnil\n<native fn clock>\n"
+    );
+}
+
+#[test]
+fn bad_arguments_are_runtime_errors() {
+    let output = run("natives_bad_arguments", "print num(true);");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Argument 1 must be a number or string but got bool\n"));
+
+    let output = run("natives_bad_number", "print num(\"1x\");");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Can't convert '1x' to a number\n"));
+
+    for text in ["inf", "-infinity", "NaN", "1e400"] {
+        let output = run("natives_non_finite", &format!("print num(\"{}\");", text));
+        assert!(output.stderr.starts_with(&format!(
+            "runtime error: Can't convert '{}' to a number\n",
+            text
+        )));
+    }
+
+    let output = run("natives_arity", "clock(1);");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Expected 0 arguments but got 1\n"));
+}
+
+#[test]
+fn exit_stops_with_the_given_code() {
+    let output = run("natives_exit", "print 1;\nexit(3);\nprint 2;\n");
+    assert_eq!(output.stdout, "1\n");
+    assert_eq!(output.code, Some(3));
+}
@@ -0,0 +1,45 @@
+mod common;
+
+use common::{jlox, run_with};
+
+fn run_legacy(name: &str, source: &str) -> common::Output {
+    run_with(&["--dialect", "legacy"], name, source)
+}
+
+#[test]
+fn legacy_truthiness() {
+    let source = r#"if (0) print "zero"; else print "not zero";
+if (1) print "one"; else print "not one";
+if (nil) print "nil"; else print "not nil";
+print 0 or "x";
+print "" and "y";
+var i = 3;
+while (i) i = i - 1;
+print i;
+"#;
+
+    // Non-boolean conditions run neither branch of an `if`.
+    assert_eq!(run_legacy("legacy_truthiness", source).stdout, "x\n\n0\n");
+}
+
+#[test]
+fn legacy_equality_only_compares_numbers() {
+    let output = run_legacy("legacy_equality", "print 1 == 1;\nprint nil == nil;");
+    assert_eq!(output.stdout, "true\n");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Operands must be numbers\n --> line 2"));
+
+    let output = run_legacy("legacy_not", "print !true;\nprint !0;");
+    assert_eq!(output.stdout, "false\n");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Operand must be a boolean\n"));
+}
+
+#[test]
+fn unknown_dialect_is_a_usage_error() {
+    let output = jlox(&["--dialect", "python", "script.lox"]);
+    assert!(output.stderr.starts_with("Usage: "));
+    assert_eq!(output.code, Some(64));
+}
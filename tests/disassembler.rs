@@ -0,0 +1,61 @@
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{compile, jlox};
+use jlox::disassembler;
+
+#[test]
+fn lists_instructions_with_operands_and_lines() {
+    let function = compile("var a = 1;\nwhile (a < 3)\n  a = a + 1;\n");
+
+    assert_eq!(
+        disassembler::disassemble(&function),
+        r#"== <script> ==
+0000    1 Constant              0 1
+0003    | DefineGlobal          1 "a"
+0006    2 GetGlobal             1 "a"
+0009    | Constant              2 3
+0012    | Less
+0013    | JumpIfFalse          13 -> 0031
+0016    | Pop
+0017    3 GetGlobal             1 "a"
+0020    | Constant              3 1
+0023    | Add
+0024    | SetGlobal             1 "a"
+0027    | Pop
+0028    2 Loop                 28 -> 0006
+0031    | Pop
+0032    3 Nil
+0033    | Return
+"#
+    );
+}
+
+#[test]
+fn nested_functions_follow_their_parent() {
+    let function = compile("fun outer() {\n  var x = 1;\n  fun inner() { return x; }\n}\n");
+    let listing = disassembler::disassemble(&function);
+
+    let headers: Vec<&str> = listing.lines().filter(|l| l.starts_with("==")).collect();
+    assert_eq!(
+        headers,
+        vec!["== <script> ==", "== <fn outer> ==", "== <fn inner> =="]
+    );
+    assert!(listing.contains("0003    3 Closure               1 <fn inner>\n"));
+    assert!(listing.contains("0006    |                    local 1\n"));
+    assert!(listing.contains("0008    4 Nil\n"));
+    assert!(listing.contains("0000    3 GetUpvalue            0\n"));
+}
+
+#[test]
+fn dump_bytecode_flag_prints_instead_of_running() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dump_bytecode.lox");
+    fs::write(&path, "print 1;\n").unwrap();
+
+    let output = jlox(&["--dump-bytecode", path.to_str().unwrap()]);
+    assert_eq!(
+        output.stdout,
+        "== <script> ==\n0000    1 Constant              0 1\n0003    | Print\n0004    | Nil\n0005    | Return\n"
+    );
+}
@@ -0,0 +1,73 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn return_hands_back_a_value() {
+    let source = r#"
+        fun square(x) { return x * x; }
+        print square(4);
+    "#;
+
+    // `return` used to be scanned as `print`, which printed 16 and returned nil.
+    assert_eq!(run("return_value", source).stdout, "16\n");
+}
+
+#[test]
+fn return_unwinds_through_blocks_and_loops() {
+    let source = r#"
+        fun find(limit) {
+            var i = 0;
+            while (true) {
+                {
+                    if (i * i > limit) {
+                        return i;
+                    }
+                }
+                i = i + 1;
+            }
+            print "unreachable";
+        }
+        print find(50);
+    "#;
+
+    assert_eq!(run("return_unwinds", source).stdout, "8\n");
+}
+
+#[test]
+fn missing_return_yields_nil() {
+    let source = r#"
+        fun nothing() {}
+        fun early() { return; print "unreachable"; }
+        print nothing();
+        print early();
+    "#;
+
+    assert_eq!(run("missing_return", source).stdout, "nil\nnil\n");
+}
+
+#[test]
+fn functions_are_values() {
+    let source = r#"
+        fun add(a, b) { return a + b; }
+        fun apply(f, x, y) { return f(x, y); }
+        var plus = add;
+        print apply(plus, 1, 2);
+        print plus;
+    "#;
+
+    assert_eq!(run("functions_are_values", source).stdout, "3\n<fn add>\n");
+}
+
+#[test]
+fn calls_check_arity_and_callee() {
+    let output = run("call_arity", "fun f(a, b) {}\nf(1, 2, 3);");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Expected 2 arguments but got 3\n --> line 2"));
+
+    let output = run("call_non_function", "var x = 1;\nx();");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Can only call functions and classes\n"));
+}
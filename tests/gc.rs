@@ -0,0 +1,142 @@
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use common::{compile, interpret, run};
+use jlox::{interpreter::Interpreter, object::GcConfig, vm::Vm};
+
+#[test]
+fn cycles_are_collected() {
+    let mut vm = Vm::with_gc(GcConfig {
+        threshold: 64,
+        stress: false,
+    });
+    vm.interpret(compile(
+        r#"class Node {
+    init() { this.next = this; }
+}
+for (var i = 0; i < 10000; i = i + 1) {
+    var a = Node();
+    var b = Node();
+    a.next = b;
+    b.next = a;
+    fun f() { return a; }
+    a.f = f;
+}
+"#,
+    ))
+    .unwrap();
+
+    let stats = vm.heap_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.freed > 30000);
+    assert!(stats.live < 128, "{} objects still alive", stats.live);
+}
+
+#[test]
+fn reachable_objects_survive_stress() {
+    let source = r#"class Counter {
+    init() { this.count = 0; }
+    inc() { this.count = this.count + 1; return this; }
+}
+fun make() {
+    var counter = Counter();
+    fun bump() { return counter.inc().count; }
+    return bump;
+}
+var bump = make();
+var list = Counter();
+for (var i = 0; i < 50; i = i + 1) {
+    bump();
+    var node = Counter();
+    node.next = list;
+    list = node;
+}
+var length = 0;
+for (var i = 0; i < 50; i = i + 1) {
+    length = length + 1;
+    list = list.next;
+}
+print bump();
+print length;
+print list.count;
+"#;
+
+    let output = run("gc_reachable_objects_survive_stress", source);
+    assert_eq!(output.stdout, "51\n50\n0\n");
+}
+
+#[test]
+fn interpreter_collects_cycles() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_gc(GcConfig {
+        threshold: 64,
+        stress: false,
+    });
+    interpret(
+        &mut interpreter,
+        r#"class Node {
+    init() { this.next = this; }
+}
+for (var i = 0; i < 10000; i = i + 1) {
+    var a = Node();
+    var b = Node();
+    a.next = b;
+    b.next = a;
+    fun f() { return a; }
+    a.f = f;
+    var list = [a];
+    push(list, list);
+    var map = {"b": b};
+    map["self"] = map;
+}
+"#,
+    )
+    .unwrap();
+
+    let stats = interpreter.heap_stats();
+    assert!(stats.collections > 0);
+    assert!(stats.freed > 40000);
+    assert!(stats.live < 128, "{} objects still tracked", stats.live);
+}
+
+#[test]
+fn interpreter_keeps_reachable_cycles_under_stress() {
+    let mut interpreter = Interpreter::new();
+    interpreter.set_gc(GcConfig {
+        threshold: 1,
+        stress: true,
+    });
+    let seen = Rc::new(RefCell::new(vec![]));
+    let record = seen.clone();
+    interpreter.define_native("record", 1, move |args| {
+        record.borrow_mut().push(args.number(0)?);
+        Ok(().into())
+    });
+    interpret(
+        &mut interpreter,
+        r#"class Counter {
+    init() { this.count = 0; this.self = this; }
+    inc() { this.count = this.count + 1; return this; }
+}
+fun make() {
+    var counter = Counter();
+    fun bump() { return counter.inc().count; }
+    return bump;
+}
+var bump = make();
+var ring = [];
+push(ring, ring);
+for (var i = 0; i < 50; i = i + 1) {
+    bump();
+    push(ring, Counter().inc());
+}
+record(bump());
+record(len(ring));
+record(ring[0][50].self.count);
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(*seen.borrow(), vec![51.0, 51.0, 1.0]);
+}
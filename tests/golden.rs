@@ -0,0 +1,129 @@
+mod common;
+
+use std::{
+    fmt::Write,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use common::jlox;
+
+// Runs every script under tests/lox and checks it against the annotations it
+// carries, in the format of the Crafting Interpreters test suite:
+//
+//   print 1 + 2; // expect: 3
+//   nil.field;   // expect runtime error: Only instances have properties
+//
+// Each `expect:` is the next line printed, a runtime error must be reported on the
+// line of its annotation. Scripts run on every backend and all failures are
+// reported together.
+const BACKENDS: [(&str, &[&str]); 3] = [
+    ("interpreter", &[]),
+    ("vm", &["--vm"]),
+    ("vm with gc stress", &["--gc-stress"]),
+];
+
+#[derive(Debug, Default, PartialEq)]
+struct Expectations {
+    stdout: Vec<String>,
+    // Message and line of the expected runtime error.
+    runtime_error: Option<(String, u32)>,
+}
+
+#[test]
+fn golden_files() {
+    let root = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/lox");
+    let mut scripts = vec![];
+    collect_scripts(&root, &mut scripts);
+    scripts.sort();
+    assert!(!scripts.is_empty(), "no scripts under {}", root.display());
+
+    let mut report = String::new();
+    let mut failures = 0;
+    for script in &scripts {
+        let expected = parse_expectations(&fs::read_to_string(script).unwrap());
+        let name = script.strip_prefix(&root).unwrap().display();
+        for (backend, flags) in BACKENDS {
+            let mut args = flags.to_vec();
+            args.push(script.to_str().unwrap());
+            let output = jlox(&args);
+
+            let diff = compare(&expected, &output);
+            if !diff.is_empty() {
+                failures += 1;
+                let _ = writeln!(report, "FAIL {} ({})\n{}", name, backend, diff);
+            }
+        }
+    }
+
+    assert!(
+        failures == 0,
+        "{} of {} golden runs failed\n\n{}",
+        failures,
+        scripts.len() * BACKENDS.len(),
+        report
+    );
+}
+
+fn collect_scripts(dir: &Path, scripts: &mut Vec<PathBuf>) {
+    for entry in fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            collect_scripts(&path, scripts);
+        } else if path.extension().is_some_and(|ext| ext == "lox") {
+            scripts.push(path);
+        }
+    }
+}
+
+fn parse_expectations(source: &str) -> Expectations {
+    let mut expectations = Expectations::default();
+    for (index, line) in source.lines().enumerate() {
+        if let Some((_, expected)) = line.split_once("// expect: ") {
+            expectations.stdout.push(expected.to_string());
+        } else if let Some((_, message)) = line.split_once("// expect runtime error: ") {
+            expectations.runtime_error = Some((message.to_string(), index as u32 + 1));
+        }
+    }
+    expectations
+}
+
+// Reads the message and line back from a rendered runtime error.
+fn runtime_error(stderr: &str) -> Option<(String, u32)> {
+    let mut lines = stderr.lines();
+    let message = lines.next()?.strip_prefix("runtime error: ")?;
+    let (_, location) = lines.next()?.split_once("--> line ")?;
+    let line = location.split(',').next()?.parse().ok()?;
+    Some((message.to_string(), line))
+}
+
+// Empty when the output matches, a line diff of what went wrong otherwise.
+fn compare(expected: &Expectations, output: &common::Output) -> String {
+    let mut diff = String::new();
+    let actual: Vec<&str> = output.stdout.lines().collect();
+    for i in 0..expected.stdout.len().max(actual.len()) {
+        let (want, got) = (expected.stdout.get(i), actual.get(i));
+        if want.map(String::as_str) != got.copied() {
+            if let Some(want) = want {
+                let _ = writeln!(diff, "  - {}", want);
+            }
+            if let Some(got) = got {
+                let _ = writeln!(diff, "  + {}", got);
+            }
+        }
+    }
+
+    match (&expected.runtime_error, runtime_error(&output.stderr)) {
+        (Some(want), Some(got)) if *want == got => {}
+        (None, _) if output.stderr.is_empty() => {}
+        (want, _) => {
+            if let Some((message, line)) = want {
+                let _ = writeln!(diff, "  - runtime error at line {}: {}", line, message);
+            }
+            for line in output.stderr.lines() {
+                let _ = writeln!(diff, "  + {}", line);
+            }
+        }
+    }
+    diff
+}
@@ -0,0 +1,87 @@
+mod common;
+
+use std::{cell::RefCell, rc::Rc};
+
+use common::interpret;
+use jlox::{error::RuntimeErrorKind, interpreter::Interpreter, value::Value};
+
+// Interpreter whose `emit` native collects its argument in the returned log.
+fn host() -> (Interpreter, Rc<RefCell<Vec<String>>>) {
+    let log = Rc::new(RefCell::new(vec![]));
+    let mut interpreter = Interpreter::new();
+
+    let emitted = log.clone();
+    interpreter.define_native("emit", 1, move |args| {
+        emitted.borrow_mut().push(args.string(0)?.to_string());
+        Ok(().into())
+    });
+    interpreter.define_native("hypot", 2, |args| {
+        let (a, b) = (args.number(0)?, args.number(1)?);
+        Ok((a * a + b * b).sqrt().into())
+    });
+    interpreter.define_native("fail", 0, |_| {
+        Err(RuntimeErrorKind::Native("host refused".to_string()))
+    });
+
+    (interpreter, log)
+}
+
+#[test]
+fn natives_are_callable_from_lox() {
+    let (mut interpreter, log) = host();
+    interpret(
+        &mut interpreter,
+        r#"fun greet(name) { emit("hello " + name); }
+greet("lox");
+var h = hypot;
+if (h(3, 4) == 5) emit("five");
+"#,
+    )
+    .unwrap();
+
+    assert_eq!(*log.borrow(), vec!["hello lox", "five"]);
+}
+
+#[test]
+fn argument_errors_point_at_the_call() {
+    let (mut interpreter, _) = host();
+
+    let err = interpret(&mut interpreter, "hypot(3, \"4\");").unwrap_err();
+    assert_eq!(
+        err.kind,
+        RuntimeErrorKind::ArgumentType {
+            position: 2,
+            expected: "number",
+            got: "string"
+        }
+    );
+    assert_eq!(
+        err.to_string(),
+        "[line 1] RuntimeError: Argument 2 must be a number but got string"
+    );
+
+    let err = interpret(&mut interpreter, "emit();").unwrap_err();
+    assert_eq!(
+        err.kind,
+        RuntimeErrorKind::ArityMismatch {
+            expected: 1,
+            got: 0
+        }
+    );
+
+    let err = interpret(&mut interpreter, "fun f() {\n  fail();\n}\nf();").unwrap_err();
+    assert_eq!(
+        err.kind,
+        RuntimeErrorKind::Native("host refused".to_string())
+    );
+    assert_eq!(err.line(), 2);
+    assert_eq!(err.trace.len(), 1);
+}
+
+#[test]
+fn values_convert_from_rust_types() {
+    assert!(matches!(Value::from(1.5), Value::Number(n) if n == 1.5));
+    assert!(matches!(Value::from("a"), Value::String(s) if s == "a"));
+    assert!(matches!(Value::from(true), Value::Bool(true)));
+    assert_eq!(Value::from(()).type_name(), "nil");
+}
@@ -0,0 +1,99 @@
+mod common;
+
+use std::{cell::RefCell, io, rc::Rc};
+
+use common::{compile, interpret};
+use jlox::{interpreter::Interpreter, vm::Vm};
+
+// Output sink the test can still read after handing it to the interpreter.
+#[derive(Clone, Default)]
+struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+impl io::Write for Buffer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Buffer {
+    fn contents(&self) -> String {
+        String::from_utf8(self.0.borrow().clone()).unwrap()
+    }
+}
+
+#[test]
+fn print_writes_to_the_given_sink() {
+    let output = Buffer::default();
+    let mut interpreter = Interpreter::with_io(output.clone(), io::empty());
+
+    interpret(&mut interpreter, "print 1 + 2;\nprint \"lox\";").unwrap();
+    interpret(&mut interpreter, "print nil;").unwrap();
+    assert_eq!(output.contents(), "3\nlox\nnil\n");
+}
+
+#[test]
+fn input_reads_lines_from_the_given_source() {
+    let output = Buffer::default();
+    let input = io::Cursor::new("first\r\nsecond\n");
+    let mut interpreter = Interpreter::with_io(output.clone(), input);
+
+    interpret(
+        &mut interpreter,
+        "var a = input();\nvar b = input();\nprint b + \" \" + a;\nprint input();",
+    )
+    .unwrap();
+    assert_eq!(output.contents(), "second first\nnil\n");
+}
+
+#[test]
+fn write_failures_are_runtime_errors() {
+    struct Closed;
+    impl io::Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut interpreter = Interpreter::with_io(Closed, io::empty());
+    let err = interpret(&mut interpreter, "\nprint 1;").unwrap_err();
+    assert_eq!(err.line(), 2);
+    assert!(err.to_string().contains("I/O error"));
+}
+
+#[test]
+fn vm_uses_the_given_streams() {
+    let output = Buffer::default();
+    let input = io::Cursor::new("first\nsecond\n");
+    let mut vm = Vm::with_io(output.clone(), input);
+
+    vm.interpret(compile(
+        "var a = input();\nprint input() + \" \" + a;\nprint input();",
+    ))
+    .unwrap();
+    assert_eq!(output.contents(), "second first\nnil\n");
+}
+
+#[test]
+fn vm_write_failures_are_runtime_errors() {
+    struct Closed;
+    impl io::Write for Closed {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::ErrorKind::BrokenPipe.into())
+        }
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    let mut vm = Vm::with_io(Closed, io::empty());
+    let err = vm.interpret(compile("\nprint 1;")).unwrap_err();
+    assert_eq!(err.line(), 2);
+    assert!(err.to_string().contains("I/O error"));
+}
@@ -0,0 +1,190 @@
+use jlox::{
+    error::{Diagnostic, Severity},
+    expression::ExpressionKind,
+    parser::Parser,
+    scanner::Scanner,
+    statement::StatementKind,
+};
+
+fn parse(source: &str) -> Result<usize, Vec<Diagnostic>> {
+    let mut scanner = Scanner::new(source.chars().collect());
+    scanner.scan().unwrap();
+    Parser::new(scanner.tokens())
+        .parse()
+        .map(|stmts| stmts.len())
+}
+
+#[test]
+fn valid_program_has_no_diagnostics() {
+    assert_eq!(parse("var a = 1;\nprint a;").unwrap(), 2);
+}
+
+#[test]
+fn reports_every_error() {
+    let diagnostics = parse("var a = ;\nprint 1 +;\nvar b = 2;\nprint (b;").unwrap_err();
+
+    let positions: Vec<(u32, u32)> = diagnostics.iter().map(|d| (d.line(), d.column())).collect();
+    assert_eq!(positions, vec![(1, 9), (2, 10), (4, 9)]);
+    assert!(diagnostics.iter().all(|d| d.severity == Severity::Error));
+    assert_eq!(diagnostics[0].message, "Expected expression");
+    assert_eq!(diagnostics[0].lexeme, ";");
+}
+
+#[test]
+fn error_at_end_of_input() {
+    let diagnostics = parse("print 1").unwrap_err();
+
+    assert_eq!(diagnostics.len(), 1);
+    assert_eq!(
+        diagnostics[0].to_string(),
+        "[line 1] Error at end: Expected ';' after value"
+    );
+}
+
+#[test]
+fn invalid_assignment_target_has_help() {
+    let diagnostics = parse("1 = 2;").unwrap_err();
+
+    assert_eq!(diagnostics[0].message, "Invalid assignment target");
+    assert_eq!(
+        diagnostics[0].help.as_deref(),
+        Some("only variables, fields, list elements and map entries can be assigned to")
+    );
+}
+
+#[test]
+fn rendered_diagnostic_underlines_lexeme() {
+    let source = "var answer = 42;\nanswer + = 1;";
+    let diagnostics = parse(source).unwrap_err();
+
+    assert_eq!(
+        diagnostics[0].render(source, false),
+        "error: Expected expression
+ --> line 2, column 10
+  |
+2 | answer + = 1;
+  |          ^
+"
+    );
+}
+
+#[test]
+fn scanner_reports_unexpected_symbols() {
+    let source = "var a = 1 @ 2;\nvar b = \"open";
+    let mut scanner = Scanner::new(source.chars().collect());
+    let diagnostics = scanner.scan().unwrap_err();
+
+    let messages: Vec<(&str, u32, u32)> = diagnostics
+        .iter()
+        .map(|d| (d.message.as_str(), d.line(), d.column()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![("Unexpected symbol", 1, 11), ("Unterminated string", 2, 9)]
+    );
+    assert!(diagnostics[1]
+        .render(source, false)
+        .ends_with("2 | var b = \"open\n  |         ^^^^^\n"));
+}
+
+#[test]
+fn scanner_reports_invalid_escapes() {
+    let source = "print \"\\q \\u{110000} \\u{41\";\nprint \"${1 +\";";
+    let mut scanner = Scanner::new(source.chars().collect());
+    let diagnostics = scanner.scan().unwrap_err();
+
+    let messages: Vec<(&str, &str, u32, u32)> = diagnostics
+        .iter()
+        .map(|d| (d.message.as_str(), d.lexeme.as_str(), d.line(), d.column()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            ("Invalid escape sequence '\\q'", "\\q", 1, 8),
+            (
+                "'110000' is not a Unicode scalar value",
+                "\\u{110000}",
+                1,
+                11
+            ),
+            (
+                "Invalid unicode escape, expected 1 to 6 hex digits in braces",
+                "\\u{41",
+                1,
+                22
+            ),
+            ("Unterminated string", "\";", 2, 13),
+            ("Unterminated string interpolation", "", 2, 15),
+        ]
+    );
+}
+
+#[test]
+fn scanner_reports_malformed_numbers() {
+    let source = "0x; 0b102; 1e; 1e+x; 12abc; 1__0; 0x_1; 1e999; 0x1_0000_0000_0000_0000;";
+    let mut scanner = Scanner::new(source.chars().collect());
+    let diagnostics = scanner.scan().unwrap_err();
+
+    let messages: Vec<(&str, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.message.as_str(), d.lexeme.as_str()))
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            ("Expected hex digits after '0x'", "0x"),
+            ("Invalid digit '2' in binary literal", "0b102"),
+            ("Expected digits in the exponent", "1e"),
+            ("Expected digits in the exponent", "1e+x"),
+            ("Invalid character 'a' in number literal", "12abc"),
+            ("'_' can only separate digits", "1__0"),
+            ("'_' can only separate digits", "0x_1"),
+            ("Number literal is too large", "1e999"),
+            ("Number literal is too large", "0x1_0000_0000_0000_0000"),
+        ]
+    );
+}
+
+#[test]
+fn nodes_carry_source_spans() {
+    let source = "var x = 1;\nprint x + foo(2,\n  3);";
+    let mut scanner = Scanner::new(source.chars().collect());
+    scanner.scan().unwrap();
+    let stmts = Parser::new(scanner.tokens()).parse().unwrap();
+
+    let print = &stmts[1];
+    assert_eq!(
+        &source[print.span.start..print.span.end],
+        "print x + foo(2,\n  3);"
+    );
+    assert_eq!((print.span.line, print.span.column), (2, 1));
+    assert_eq!((print.span.end_line, print.span.end_column), (3, 6));
+
+    let StatementKind::Print(expr) = &print.kind else {
+        panic!("expected print statement");
+    };
+    let ExpressionKind::Binary(_, _, call) = &expr.kind else {
+        panic!("expected binary expression");
+    };
+    assert_eq!(&source[call.span.start..call.span.end], "foo(2,\n  3)");
+    assert_eq!((call.span.line, call.span.column), (2, 11));
+}
+
+#[test]
+fn break_and_continue_only_inside_loops() {
+    let diagnostics =
+        parse("break;\nwhile (true) { fun f() { continue; } break; }\nfor (;;) continue;")
+            .unwrap_err();
+
+    let errors: Vec<(u32, &str)> = diagnostics
+        .iter()
+        .map(|d| (d.line(), d.message.as_str()))
+        .collect();
+    assert_eq!(
+        errors,
+        vec![
+            (1, "Can't use 'break' outside of a loop"),
+            (2, "Can't use 'continue' outside of a loop"),
+        ]
+    );
+}
@@ -0,0 +1,106 @@
+use std::{
+    io::Write,
+    path::PathBuf,
+    process::{Command, Stdio},
+};
+
+use jlox::{
+    expression::ExpressionKind,
+    repl::{nesting_depth, parse_input},
+    statement::StatementKind,
+};
+
+#[test]
+fn nesting_depth_counts_open_brackets() {
+    assert_eq!(nesting_depth("fun f() {\n"), 1);
+    assert_eq!(nesting_depth("var l = [1, (2"), 2);
+    assert_eq!(nesting_depth("{ [ ( ) ] }"), 0);
+    assert_eq!(nesting_depth("}"), -1);
+}
+
+#[test]
+fn nesting_depth_skips_strings_and_comments() {
+    assert_eq!(nesting_depth("print \"{ ( [\";"), 0);
+    assert_eq!(nesting_depth("print \"say \\\"{\\\"\";"), 0);
+    assert_eq!(nesting_depth("print \"\\\\\"; {"), 1);
+    assert_eq!(nesting_depth("fun f() { // } closes nothing\n"), 1);
+    assert_eq!(nesting_depth("var half = 1 / 2; {"), 1);
+}
+
+fn kinds(source: &str) -> Vec<&'static str> {
+    parse_input(source)
+        .unwrap()
+        .iter()
+        .map(|stmt| match &stmt.kind {
+            StatementKind::Expression(expr) if matches!(expr.kind, ExpressionKind::Map(_)) => "map",
+            StatementKind::Expression(_) => "expression",
+            StatementKind::Block(_) => "block",
+            StatementKind::Variable(..) => "var",
+            StatementKind::Print(_) => "print",
+            _ => "other",
+        })
+        .collect()
+}
+
+#[test]
+fn bare_expressions_need_no_semicolon() {
+    assert_eq!(kinds("1 + 2"), ["expression"]);
+    assert_eq!(kinds("1 + 2;"), ["expression"]);
+    assert_eq!(kinds("{\"k\": 1}"), ["map"]);
+    assert_eq!(kinds("{}"), ["map"]);
+    assert_eq!(kinds("{ print 1; }"), ["block"]);
+}
+
+#[test]
+fn missing_final_semicolon_is_forgiven() {
+    assert_eq!(kinds("var a = 1"), ["var"]);
+    assert_eq!(kinds("var a = 1; print a"), ["var", "print"]);
+
+    let Err(diagnostics) = parse_input("var = 1") else {
+        panic!("expected a parse error");
+    };
+    assert_eq!(diagnostics[0].message, "Expected variable name");
+}
+
+#[test]
+fn piped_session_echoes_values_without_prompts() {
+    let home = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("repl_home");
+    std::fs::create_dir_all(&home).unwrap();
+    let mut child = Command::new(env!("CARGO_BIN_EXE_jlox"))
+        .env("HOME", &home)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+
+    let session = r#"class Point {
+  init(x, y) {
+    this.x = x;
+    this.y = y;
+  }
+  sum() { return this.x + this.y; }
+}
+var p = Point(1, 2)
+p.sum()
+fun twice(n) {
+  return n * 2;
+}
+twice(p.sum())
+{"k": [1, 2]}
+print "done";
+"#;
+    child
+        .stdin
+        .take()
+        .unwrap()
+        .write_all(session.as_bytes())
+        .unwrap();
+    let output = child.wait_with_output().unwrap();
+
+    assert_eq!(String::from_utf8(output.stderr).unwrap(), "");
+    assert_eq!(
+        String::from_utf8(output.stdout).unwrap(),
+        "3\n6\n{\"k\": [1, 2]}\ndone\n"
+    );
+}
@@ -0,0 +1,52 @@
+mod common;
+
+use common::run;
+
+#[test]
+fn reading_local_in_its_own_initializer() {
+    let source = r#"
+        var a = "outer";
+        {
+            var a = a;
+        }
+    "#;
+
+    let output = run("own_initializer", source);
+    assert!(output
+        .stderr
+        .contains("Can't read local variable in its own initializer"));
+    assert!(output.stdout.is_empty());
+}
+
+#[test]
+fn redeclaring_local_in_same_scope() {
+    let source = r#"
+        fun f() {
+            var a = 1;
+            var a = 2;
+        }
+    "#;
+
+    let output = run("redeclare_local", source);
+    assert!(output
+        .stderr
+        .contains("Already a variable with this name in this scope"));
+}
+
+#[test]
+fn redeclaring_global_is_allowed() {
+    let source = r#"
+        var a = 1;
+        var a = 2;
+        print a;
+    "#;
+
+    assert_eq!(run("redeclare_global", source).stdout, "2\n");
+}
+
+#[test]
+fn return_from_top_level() {
+    let output = run("top_level_return", "return 1;");
+    assert_eq!(output.code, Some(65));
+    assert!(output.stderr.contains("Can't return from top-level code"));
+}
@@ -0,0 +1,122 @@
+mod common;
+
+use common::{interpret, run};
+use jlox::{
+    error::{RuntimeErrorKind, TraceFrame},
+    interpreter::Interpreter,
+    parser::Parser,
+    scanner::Scanner,
+};
+
+#[test]
+fn error_points_at_operator() {
+    let source = "var a = 1;\nvar b = a -  \"x\";";
+    let err = interpret(&mut Interpreter::new(), source).unwrap_err();
+
+    assert_eq!(err.kind, RuntimeErrorKind::OperandsMustBeNumbers);
+    assert_eq!(err.line(), 2);
+    assert_eq!(err.column(), 11);
+    assert_eq!(&source[err.span.start..err.span.end], "-");
+}
+
+#[test]
+fn error_records_call_stack() {
+    let source = r#"fun inner() {
+    return undefined;
+}
+fun outer() {
+    inner();
+}
+outer();"#;
+    let err = interpret(&mut Interpreter::new(), source).unwrap_err();
+
+    assert_eq!(
+        err.kind,
+        RuntimeErrorKind::UndefinedVariable("undefined".to_string())
+    );
+    assert_eq!(err.line(), 2);
+    assert_eq!(
+        err.trace,
+        vec![
+            TraceFrame {
+                function: "inner".to_string(),
+                line: 5,
+            },
+            TraceFrame {
+                function: "outer".to_string(),
+                line: 7,
+            },
+        ]
+    );
+}
+
+#[test]
+fn error_is_printed_with_line() {
+    let source = "fun f(a) {}\nf(1, 2);";
+    let output = run("arity_mismatch", source);
+    assert_eq!(output.code, Some(70));
+
+    assert_eq!(
+        output.stderr,
+        "runtime error: Expected 1 arguments but got 2
+ --> line 2, column 7
+  |
+2 | f(1, 2);
+  |       ^
+"
+    );
+}
+
+#[test]
+fn rendered_error_shows_hint_and_trace() {
+    let source = "fun f(x) {\n    return x + nil;\n}\nf(1);";
+    let rendered = interpret(&mut Interpreter::new(), source)
+        .unwrap_err()
+        .render(source, false);
+
+    assert_eq!(
+        rendered,
+        "runtime error: Operands must be two numbers or two strings
+ --> line 2, column 14
+  |
+2 |     return x + nil;
+  |              ^
+  = help: '+' adds two numbers or concatenates two strings
+  = in f() called from line 4
+"
+    );
+}
+
+#[test]
+fn deep_recursion_overflows_the_same_on_every_backend() {
+    let source =
+        "fun f(n) {\n    if (n == 0) return 0;\n    return 1 + f(n - 1);\n}\nprint f(5000);";
+    let output = run("deep_recursion", source);
+
+    assert_eq!(output.stdout, "");
+    assert_eq!(
+        output.stderr,
+        "runtime error: Stack overflow
+ --> line 3, column 23
+  |
+3 |     return 1 + f(n - 1);
+  |                       ^
+  = in f() called from line 3 (1021 more times)
+  = in f() called from line 5
+"
+    );
+}
+
+#[test]
+fn unresolved_top_level_return_is_an_error() {
+    for source in ["print 1;\n{ return 1; }", "print 1;\nif (true) return;"] {
+        // Skips the resolver, which would reject the program.
+        let mut scanner = Scanner::new(source.chars().collect());
+        scanner.scan().unwrap();
+        let stmts = Parser::new(scanner.tokens()).parse().unwrap();
+
+        let err = Interpreter::new().interpret_statements(stmts).unwrap_err();
+        assert_eq!(err.kind, RuntimeErrorKind::TopLevelReturn);
+        assert_eq!((err.line(), err.column()), (2, 1));
+    }
+}
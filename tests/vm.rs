@@ -0,0 +1,97 @@
+mod common;
+
+use std::{fs, path::PathBuf};
+
+use common::{compile, jlox, run};
+use jlox::vm::Vm;
+
+#[test]
+fn loops_and_operators() {
+    let source = r#"var sum = 0;
+for (var i = 0; i < 100; i = i + 1) {
+    if (i >= 50 and !(i == 75)) sum = sum + i;
+}
+print sum;
+print 7 / 2 - 1 * 3;
+print "a" + "b";
+print nil or "default";
+"#;
+
+    let output = run("vm_loops_and_operators", source);
+    assert_eq!(output.stdout, "3650\n0.5\nab\ndefault\n");
+}
+
+#[test]
+fn closures_capture_each_loop_variable() {
+    let source = r#"var first;
+var second;
+for (var i = 1; i <= 2; i = i + 1) {
+    var j = i * 10;
+    fun show() { print j; }
+    if (i == 1) first = show; else second = show;
+}
+first();
+second();
+"#;
+
+    let output = run("vm_closures_in_loop", source);
+    assert_eq!(output.stdout, "10\n20\n");
+}
+
+#[test]
+fn uninitialized_captured_variable() {
+    let source = r#"{
+    var a;
+    fun read() { return a; }
+    a = "set";
+    print read();
+    var b;
+    fun early() { return b; }
+    early();
+}
+"#;
+
+    let output = run("vm_uninitialized_capture", source);
+    assert_eq!(output.stdout, "set\n");
+    assert!(output
+        .stderr
+        .starts_with("runtime error: Usage of uninitialized variable 'b'\n --> line 7, column 26"));
+}
+
+#[test]
+fn deep_recursion_overflows_the_stack() {
+    let path = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("vm_stack_overflow.lox");
+    fs::write(&path, "fun f() { f(); }\nf();\n").unwrap();
+
+    let output = jlox(&["--vm", path.to_str().unwrap()]);
+    assert!(output.stderr.starts_with("runtime error: Stack overflow\n"));
+    assert!(output.stderr.ends_with(
+        "= in f() called from line 1 (1021 more times)\n  = in f() called from line 2\n"
+    ));
+}
+
+#[test]
+fn rejects_unknown_flags() {
+    let output = jlox(&["--fast", "script.lox"]);
+    assert!(output.stderr.starts_with(
+        "Usage: jlox [--vm] [--dump-bytecode] [--gc-stress] [--gc-threshold <objects>]\n"
+    ));
+    assert!(output
+        .stderr
+        .contains("--dump-bytecode, --gc-stress and --gc-threshold run the script on the VM."));
+}
+
+#[test]
+fn globals_survive_a_runtime_error() {
+    let mut vm = Vm::new();
+    let err = vm
+        .interpret(compile(
+            "var f;\nfun mk() { var x = 1; fun g() { return x; } f = g; nil(); }\nmk();",
+        ))
+        .unwrap_err();
+    assert_eq!(err.line(), 2);
+
+    // The captured variable was closed over when the stack was unwound.
+    vm.interpret(compile("var y = 2;\nif (f() + y != 3) nil();"))
+        .unwrap();
+}